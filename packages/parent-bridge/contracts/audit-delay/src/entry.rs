@@ -11,46 +11,338 @@ use alloc::{vec, vec::Vec};
 use ckb_std::{
     ckb_constants::Source,
     debug,
-    high_level::{load_script, load_tx_hash, load_header, load_cell_lock_hash},
-    ckb_types::{bytes::Bytes, prelude::*},
+    high_level::{
+        load_script, load_tx_hash, load_header, load_cell_lock_hash, load_input_since, load_cell_type_hash,
+        load_cell_capacity, load_cell_data, load_script_hash, load_cell_lock, load_transaction, QueryIter,
+    },
+    ckb_types::{bytes::Bytes, packed::Script, prelude::*},
 };
 
 use crate::error::Error;
 
+// `since` encoding, see https://github.com/nervosnetwork/rfcs/blob/master/rfcs/0017-tx-valid-since/0017-tx-valid-since.md
+const SINCE_RELATIVE_FLAG: u64 = 0x8000_0000_0000_0000;
+const SINCE_METRIC_MASK: u64 = 0x6000_0000_0000_0000;
+const SINCE_METRIC_EPOCH: u64 = 0x2000_0000_0000_0000;
+const SINCE_METRIC_TIMESTAMP: u64 = 0x4000_0000_0000_0000;
+const SINCE_RESERVED_MASK: u64 = 0x1f00_0000_0000_0000;
+const SINCE_VALUE_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+
+// cell data is the receipt hash (32 bytes), optionally followed by a status byte
+const RECEIPT_HASH_LEN: usize = 32;
+// token payouts carry the asset as type script and put the sUDT amount (16 bytes) in front
+const UDT_AMOUNT_LEN: usize = 16;
+const STATUS_PENDING: u8 = 0;
+const STATUS_FROZEN: u8 = 1;
+const STATUS_RELEASED: u8 = 2;
+
+// action byte the bridge reads from the first witness when it takes vetoed payouts back
+const ACTION_RETURN_VETOED_PAYOUT: u8 = 3;
+
+// share of a matured payout a third party may spend on fees when finalizing it (shannons)
+const MAX_FINALIZE_FEE: u64 = 100_000;
+
 pub fn main() -> Result<(), Error> {
-    let input0_lock_hash = load_cell_lock_hash(0, Source::Input)?;
     let script = load_script()?;
     let args: Bytes = script.args().unpack();
 
-    if args.len() != 72 {
+    // restricted args additionally name the bridge a vetoed payout has to go back to
+    if args.len() != 72 && args.len() != 104 {
         return Err(Error::WrongScriptArgsLength);
     }
 
     let trustee_lock_hash = args.slice(0..32);
-    let owner_lock_hash = args.slice(32..64);
+    let owner_hash = args.slice(32..64);
 
-    if Bytes::from(&input0_lock_hash[..]) == trustee_lock_hash {
+    // the bridge cell takes input 0 when a veto is returned, so the trustee may sign with any input
+    let trustee_signed = QueryIter::new(load_cell_lock_hash, Source::Input)
+        .any(|hash| Bytes::from(&hash[..]) == trustee_lock_hash);
+    if trustee_signed {
+        let own_hash = load_script_hash()?;
+        let is_recreated = QueryIter::new(load_cell_lock_hash, Source::Output).any(|hash| hash == own_hash);
+        if is_recreated {
+            return verify_recreated(&own_hash);
+        }
+        if args.len() == 104 {
+            return verify_returned_to_bridge(&args.slice(72..104), &trustee_lock_hash);
+        }
         return Ok(());
     }
 
-    let input_header = load_header(0, Source::GroupInput)?;
-    let input_header_timestamp = input_header.raw().timestamp().unpack();
-    let proof_header = load_header(1, Source::HeaderDep)?;
-    let proof_header_timestamp = proof_header.raw().timestamp().unpack();
     let timeout = args.slice(64..72);
     let timeout_array: [u8; 8] = (&*timeout).try_into().unwrap();
     let timeout_num = u64::from_be_bytes(timeout_array);
-    
-    if proof_header_timestamp - input_header_timestamp < timeout_num {
-        return Err(Error::NotEnoughTimePassed);
+
+    for (index, since) in QueryIter::new(load_input_since, Source::GroupInput).enumerate() {
+        match load_status(index)? {
+            STATUS_FROZEN => return Err(Error::PayoutFrozen),
+            // the trustee has cleared the payout, no need to wait any longer
+            STATUS_RELEASED => continue,
+            _ => verify_delay(index, since, timeout_num)?,
+        }
     }
 
-    let is_correct_lock_hash = Bytes::from(&input0_lock_hash[..]) == owner_lock_hash;
+    // like deposit-lock, the owner is either a lock or a type script (e.g. a DEX or vault)
+    let is_correct_lock_hash = QueryIter::new(load_cell_lock_hash, Source::Input)
+        .any(|hash| Bytes::from(&hash[..]) == owner_hash);
+    let is_correct_type_hash = QueryIter::new(load_cell_type_hash, Source::Input)
+        .any(|hash| hash.map_or(false, |h| Bytes::from(&h[..]) == owner_hash));
+
+    if !(is_correct_lock_hash || is_correct_type_hash) {
+        // only the owner can tell where their tokens should go
+        let has_token_payout = QueryIter::new(load_cell_type_hash, Source::GroupInput).any(|hash| hash.is_some());
+        if has_token_payout {
+            return Err(Error::NotSpentWithOwnerInput);
+        }
+        return verify_finalized(&script, &owner_hash);
+    }
 
-    if !is_correct_lock_hash {
+    Ok(())
+}
+
+/// Once matured, anyone may finalize payouts by sending them to the exact owner lock,
+/// keeping at most `MAX_FINALIZE_FEE` for the transaction fee.
+fn verify_finalized(script: &Script, owner_hash: &[u8]) -> Result<(), Error> {
+    // all payouts to this owner in the transaction, not only this script group,
+    // so one owner output can't be counted for several payouts
+    let is_payout_to_owner = |lock: &Script| {
+        lock.code_hash().as_slice() == script.code_hash().as_slice()
+            && lock.hash_type().as_slice() == script.hash_type().as_slice()
+            && lock.args().raw_data().get(32..64) == Some(owner_hash)
+    };
+    let payout_cap: u64 = QueryIter::new(load_cell_lock, Source::Input)
+        .zip(QueryIter::new(load_cell_capacity, Source::Input))
+        .filter(|(lock, _)| is_payout_to_owner(lock))
+        .map(|(_, cap)| cap)
+        .sum();
+
+    let owner_outputs: Vec<u64> = QueryIter::new(load_cell_lock_hash, Source::Output)
+        .zip(QueryIter::new(load_cell_type_hash, Source::Output))
+        .zip(QueryIter::new(load_cell_capacity, Source::Output))
+        .filter(|((lock_hash, type_hash), _)| lock_hash[..] == *owner_hash && type_hash.is_none())
+        .map(|(_, cap)| cap)
+        .collect();
+    if owner_outputs.len() == 0 {
         return Err(Error::NotSpentWithOwnerInput);
     }
-    
+
+    let paid_cap: u64 = owner_outputs.iter().sum();
+    if paid_cap < payout_cap.saturating_sub(MAX_FINALIZE_FEE) {
+        return Err(Error::FinalizeFeeTooHigh);
+    }
+    Ok(())
+}
+
+fn load_status(index: usize) -> Result<u8, Error> {
+    let data = load_cell_data(index, Source::GroupInput)?;
+    let offset = receipt_hash_offset(index)?;
+    Ok(data.get(offset + RECEIPT_HASH_LEN).copied().unwrap_or(STATUS_PENDING))
+}
+
+/// Where the receipt hash starts in the data of a payout in this script group
+fn receipt_hash_offset(index: usize) -> Result<usize, Error> {
+    match load_cell_type_hash(index, Source::GroupInput)? {
+        Some(_) => Ok(UDT_AMOUNT_LEN),
+        None => Ok(0),
+    }
+}
+
+/// The trustee may recreate a single payout with the same lock, which restarts the
+/// delay, to freeze it for an investigation or to release it to the owner.
+fn verify_recreated(own_hash: &[u8; 32]) -> Result<(), Error> {
+    if QueryIter::new(load_cell_capacity, Source::GroupInput).count() != 1 {
+        return Err(Error::InvalidRecreatedPayout);
+    }
+    let capacity = load_cell_capacity(0, Source::GroupInput)?;
+    let type_hash = load_cell_type_hash(0, Source::GroupInput)?;
+    let data = load_cell_data(0, Source::GroupInput)?;
+    // the sUDT amount of a token payout stays as it is, like the receipt hash
+    let receipt_hash = &data[..core::cmp::min(data.len(), receipt_hash_offset(0)? + RECEIPT_HASH_LEN)];
+
+    let recreated: Vec<(u64, Option<[u8; 32]>, Vec<u8>)> = QueryIter::new(load_cell_lock_hash, Source::Output)
+        .zip(QueryIter::new(load_cell_type_hash, Source::Output))
+        .zip(QueryIter::new(load_cell_capacity, Source::Output))
+        .zip(QueryIter::new(load_cell_data, Source::Output))
+        .filter(|(((hash, _), _), _)| hash == own_hash)
+        .map(|(((_, type_hash), cap), data)| (cap, type_hash, data))
+        .collect();
+    if recreated.len() != 1 {
+        return Err(Error::InvalidRecreatedPayout);
+    }
+
+    let (recreated_capacity, recreated_type_hash, recreated_data) = &recreated[0];
+    let expected_len = receipt_hash.len() + 1;
+    if *recreated_capacity != capacity
+        || *recreated_type_hash != type_hash
+        || recreated_data.len() != expected_len
+        || recreated_data[..receipt_hash.len()] != *receipt_hash
+    {
+        return Err(Error::InvalidRecreatedPayout);
+    }
+    match recreated_data[receipt_hash.len()] {
+        STATUS_FROZEN | STATUS_RELEASED => Ok(()),
+        _ => Err(Error::InvalidRecreatedPayout),
+    }
+}
+
+/// A vetoed payout has to go back into the bridge in full, the bridge type script
+/// verifies the rest of the transition.
+fn verify_returned_to_bridge(bridge_type_hash: &[u8], trustee_lock_hash: &[u8]) -> Result<(), Error> {
+    let is_bridge = |hash: &Option<[u8; 32]>| hash.map_or(false, |h| h[..] == *bridge_type_hash);
+    // a deposit collected in the same transaction would otherwise pass for the returned payout
+    if !QueryIter::new(load_cell_type_hash, Source::Input).any(|hash| is_bridge(&hash)) {
+        return Err(Error::VetoNotReturnedToBridge);
+    }
+    let action = load_transaction()?
+        .witnesses()
+        .get(0)
+        .and_then(|witness| witness.raw_data().first().copied());
+    if action != Some(ACTION_RETURN_VETOED_PAYOUT) {
+        return Err(Error::VetoNotReturnedByBridge);
+    }
+    let bridge_cap_before: u64 = QueryIter::new(load_cell_type_hash, Source::Input)
+        .zip(QueryIter::new(load_cell_capacity, Source::Input))
+        .filter(|(hash, _)| is_bridge(hash))
+        .map(|(_, cap)| cap)
+        .sum();
+    let bridge_outputs: Vec<u64> = QueryIter::new(load_cell_type_hash, Source::Output)
+        .zip(QueryIter::new(load_cell_capacity, Source::Output))
+        .filter(|(hash, _)| is_bridge(hash))
+        .map(|(_, cap)| cap)
+        .collect();
+    // the bridge takes vetoed tokens back into its asset cells, only CKBytes go into the bridge cell
+    let vetoed_cap: u64 = QueryIter::new(load_cell_type_hash, Source::GroupInput)
+        .zip(QueryIter::new(load_cell_capacity, Source::GroupInput))
+        .filter(|(hash, _)| hash.is_none())
+        .map(|(_, cap)| cap)
+        .sum();
+
+    if bridge_outputs.len() == 0 {
+        return Err(Error::VetoNotReturnedToBridge);
+    }
+    let bridge_cap_after: u64 = bridge_outputs.iter().sum();
+    if bridge_cap_after < bridge_cap_before + vetoed_cap {
+        return Err(Error::VetoNotReturnedToBridge);
+    }
+
+    // vetoed tokens go into the asset cells, which the bridge guards for itself and its trustee
+    let mut vetoed_tokens: Vec<([u8; 32], u128)> = Vec::new();
+    for (hash, data) in QueryIter::new(load_cell_type_hash, Source::GroupInput)
+        .zip(QueryIter::new(load_cell_data, Source::GroupInput))
+    {
+        if let Some(asset) = hash {
+            let amount = udt_amount(&data).ok_or(Error::Encoding)?;
+            match vetoed_tokens.iter_mut().find(|(vetoed_asset, _)| *vetoed_asset == asset) {
+                Some((_, total)) => {
+                    *total = total.checked_add(amount).ok_or(Error::VetoedTokensNotReturned)?;
+                }
+                None => vetoed_tokens.push((asset, amount)),
+            }
+        }
+    }
+    let guard_args = [bridge_type_hash, trustee_lock_hash].concat();
+    for (asset, amount) in vetoed_tokens.iter() {
+        let before = guarded_token_amount(Source::Input, &guard_args, asset)?;
+        let after = guarded_token_amount(Source::Output, &guard_args, asset)?;
+        if after < before.checked_add(*amount).ok_or(Error::VetoedTokensNotReturned)? {
+            return Err(Error::VetoedTokensNotReturned);
+        }
+    }
     Ok(())
 }
 
+/// Total sUDT amount of `asset` in the cells of `source` locked with `guard_args`
+fn guarded_token_amount(source: Source, guard_args: &[u8], asset: &[u8; 32]) -> Result<u128, Error> {
+    QueryIter::new(load_cell_lock, source)
+        .zip(QueryIter::new(load_cell_type_hash, source))
+        .zip(QueryIter::new(load_cell_data, source))
+        .filter(|((lock, hash), _)| lock.args().raw_data()[..] == *guard_args && hash.as_ref() == Some(asset))
+        .try_fold(0u128, |total, (_, data)| {
+            total
+                .checked_add(udt_amount(&data).unwrap_or(0))
+                .ok_or(Error::VetoedTokensNotReturned)
+        })
+}
+
+/// sUDT amount in front of the cell data
+fn udt_amount(data: &[u8]) -> Option<u128> {
+    let amount: [u8; UDT_AMOUNT_LEN] = data.get(..UDT_AMOUNT_LEN)?.try_into().ok()?;
+    Some(u128::from_le_bytes(amount))
+}
+
+/// The timeout is either a relative `since` value (relative flag set), which the
+/// input has to commit to, or a legacy duration in milliseconds. The latter can be
+/// proven with a relative timestamp `since` or, if the input has none, with header deps.
+fn verify_delay(index: usize, since: u64, timeout: u64) -> Result<(), Error> {
+    if timeout & SINCE_RELATIVE_FLAG != 0 {
+        return verify_since(since, timeout);
+    }
+    if since != 0 {
+        // since timestamps count seconds, round the timeout up
+        let seconds = timeout / 1000 + (timeout % 1000 != 0) as u64;
+        return verify_since(since, SINCE_RELATIVE_FLAG | SINCE_METRIC_TIMESTAMP | seconds);
+    }
+    verify_header_delay(index, timeout)
+}
+
+fn verify_since(since: u64, required: u64) -> Result<(), Error> {
+    if since & SINCE_RELATIVE_FLAG == 0 || since & SINCE_RESERVED_MASK != 0 {
+        return Err(Error::InvalidSince);
+    }
+    let metric = since & SINCE_METRIC_MASK;
+    if metric != required & SINCE_METRIC_MASK || metric == SINCE_METRIC_MASK {
+        return Err(Error::InvalidSince);
+    }
+
+    let value = since & SINCE_VALUE_MASK;
+    let required_value = required & SINCE_VALUE_MASK;
+    let has_passed = if metric == SINCE_METRIC_EPOCH {
+        epoch_has_passed(value, required_value)?
+    } else {
+        value >= required_value
+    };
+
+    if !has_passed {
+        return Err(Error::NotEnoughTimePassed);
+    }
+    Ok(())
+}
+
+/// Epoch values are fractions (number + index / length), compare them by cross multiplication.
+fn epoch_has_passed(value: u64, required: u64) -> Result<bool, Error> {
+    let (numerator, denominator) = epoch_fraction(value)?;
+    let (required_numerator, required_denominator) = epoch_fraction(required)?;
+    Ok(numerator * required_denominator >= required_numerator * denominator)
+}
+
+fn epoch_fraction(value: u64) -> Result<(u128, u128), Error> {
+    let number = value & 0xff_ffff;
+    let index = (value >> 24) & 0xffff;
+    let length = (value >> 40) & 0xffff;
+    if length == 0 || index >= length {
+        return Err(Error::InvalidSince);
+    }
+    Ok(((number * length + index) as u128, length as u128))
+}
+
+fn verify_header_delay(index: usize, timeout: u64) -> Result<(), Error> {
+    let input_header = load_header(index, Source::GroupInput)?;
+    let input_header_timestamp: u64 = input_header.raw().timestamp().unpack();
+    // any header dep can serve as proof, the most recent one counts
+    let proof_header_timestamp = QueryIter::new(load_header, Source::HeaderDep)
+        .map(|header| {
+            let timestamp: u64 = header.raw().timestamp().unpack();
+            timestamp
+        })
+        .max()
+        .ok_or(Error::NotEnoughTimePassed)?;
+
+    let elapsed = proof_header_timestamp
+        .checked_sub(input_header_timestamp)
+        .ok_or(Error::NotEnoughTimePassed)?;
+
+    if elapsed < timeout {
+        return Err(Error::NotEnoughTimePassed);
+    }
+    Ok(())
+}
@@ -11,6 +11,13 @@ pub enum Error {
     NotEnoughTimePassed,
     NotSpentWithOwnerInput,
     WrongScriptArgsLength,
+    InvalidSince,
+    VetoNotReturnedToBridge,
+    PayoutFrozen,
+    InvalidRecreatedPayout,
+    FinalizeFeeTooHigh,
+    VetoNotReturnedByBridge,
+    VetoedTokensNotReturned,
 }
 
 impl From<SysError> for Error {
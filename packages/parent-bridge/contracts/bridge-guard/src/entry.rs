@@ -0,0 +1,38 @@
+// Import from `core` instead of from `std` since we are in no-std mode
+use core::result::Result;
+
+use ckb_std::{
+    high_level::{load_script, load_cell_lock_hash, load_cell_type_hash, QueryIter},
+    ckb_types::{bytes::Bytes, prelude::*},
+    ckb_constants::Source,
+};
+
+use crate::error::Error;
+
+pub fn main() -> Result<(), Error> {
+
+    let script = load_script()?;
+    let args: Bytes = script.args().unpack();
+
+    if args.len() != 64 {
+        return Err(Error::WrongScriptArgsLength);
+    }
+
+    let bridge_type_hash = args.slice(0..32);
+    let trustee_lock_hash = args.slice(32..64);
+
+    // the bridge type script verifies every transition that keeps the bridge alive, it
+    // constrains the bridge cells of its script group wherever they are in the transaction
+    let is_bridge = |hash: Option<[u8; 32]>| hash.map_or(false, |h| bridge_type_hash == Bytes::from(&h[..]));
+    let is_bridge_in_inputs = QueryIter::new(load_cell_type_hash, Source::Input).any(is_bridge);
+    let is_bridge_in_outputs = QueryIter::new(load_cell_type_hash, Source::Output).any(is_bridge);
+    // dissolving the bridge needs the trustee
+    let is_signed_by_trustee = QueryIter::new(load_cell_lock_hash, Source::Input)
+        .any(|hash| trustee_lock_hash == Bytes::from(&hash[..]));
+
+    if !((is_bridge_in_inputs && is_bridge_in_outputs) || is_signed_by_trustee) {
+        return Err(Error::BridgeNotInOutputs);
+    }
+
+    Ok(())
+}
@@ -2,33 +2,87 @@
 pub use blake2b_rs::{Blake2b, Blake2bBuilder};
 
 use std::{
-    fs::File,
+    env,
+    fs::{self, File},
     io::{BufWriter, Read, Write},
     path::Path,
 };
 
 const BUF_SIZE: usize = 8 * 1024;
 const CKB_HASH_PERSONALIZATION: &[u8] = b"ckb-default-hash";
+// script hash types, see https://github.com/nervosnetwork/rfcs/blob/master/rfcs/0022-transaction-structure/0022-transaction-structure.md
+const HASH_TYPE_DATA: u8 = 0;
+const HASH_TYPE_TYPE: u8 = 1;
+
+const HELPERS: [&str; 3] = ["deposit-lock", "audit-delay", "bridge-guard"];
 
 fn main() {
+    // hash the helper binaries of the profile we are built with, so a release bridge
+    // accepts the release helpers that get deployed
+    let profile = env::var("PROFILE").expect("cargo sets PROFILE");
+    let build_dir = Path::new("../../build").join(&profile);
+
     let out_path = Path::new("src").join("code_hashes.rs");
     let mut out_file = BufWriter::new(File::create(&out_path).expect("create code_hashes.rs"));
-    
-    write_code_hash("deposit-lock", &mut out_file);
-    write_code_hash("anyone-can-spend", &mut out_file);
-    write_code_hash("audit-delay", &mut out_file);
+    // the resolved hashes are also left next to the binaries, the tests cross-check them
+    fs::create_dir_all(&build_dir).expect("create build dir");
+    let artifact_path = build_dir.join("parent-bridge.code_hashes");
+    let mut artifact_file = BufWriter::new(File::create(&artifact_path).unwrap_or_else(|err| {
+        panic!("create {}: {}", artifact_path.display(), err)
+    }));
+
+    for name in HELPERS.iter() {
+        write_code_hash(name, &build_dir, &mut out_file, &mut artifact_file);
+    }
 }
 
-fn write_code_hash(name: &str, mut out_file: &mut BufWriter<File>) {
+fn write_code_hash(
+    name: &str,
+    build_dir: &Path,
+    mut out_file: &mut BufWriter<File>,
+    artifact_file: &mut BufWriter<File>,
+) {
+    let const_name = name.to_uppercase().replace("-", "_");
+
+    // helper scripts deployed with Type ID are referenced by the hash of their type script,
+    // e.g. TYPE_ID_AUDIT_DELAY=0x...
+    let type_id_var = format!("TYPE_ID_{}", const_name);
+    println!("cargo:rerun-if-env-changed={}", type_id_var);
+    let (hash, hash_type) = match env::var(&type_id_var) {
+        Ok(type_id) => (parse_hash(&type_id), HASH_TYPE_TYPE),
+        Err(_) => (hash_binary(&build_dir.join(name)), HASH_TYPE_DATA),
+    };
 
-    // TODO - make this work both for debug and release
-    let path = format!("../../build/debug/{}", name);
+    write!(
+        &mut out_file,
+        "pub const CODE_HASH_{}: [u8; 32] = {:?};\npub const HASH_TYPE_{}: u8 = {};\n",
+        const_name,
+        hash,
+        const_name,
+        hash_type
+    )
+        .expect("write to code_hashes.rs");
+
+    // one line per helper: name, code hash and hash type
+    let hash_hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    writeln!(artifact_file, "{} 0x{} {}", name, hash_hex, hash_type)
+        .expect("write code hashes artifact");
+}
+
+fn hash_binary(path: &Path) -> [u8; 32] {
+    println!("cargo:rerun-if-changed={}", path.display());
 
     let mut buf = [0u8; BUF_SIZE];
 
     // build hash
     let mut blake2b = new_blake2b();
-    let mut fd = File::open(&path).expect("open file");
+    let mut fd = File::open(path).unwrap_or_else(|err| {
+        panic!(
+            "open {}: {}, helper contracts have to be built before parent-bridge",
+            path.display(),
+            err
+        )
+    });
     loop {
         let read_bytes = fd.read(&mut buf).expect("read file");
         if read_bytes > 0 {
@@ -40,14 +94,17 @@ fn write_code_hash(name: &str, mut out_file: &mut BufWriter<File>) {
 
     let mut hash = [0u8; 32];
     blake2b.finalize(&mut hash);
+    hash
+}
 
-    write!(
-        &mut out_file,
-        "pub const {}: [u8; 32] = {:?};\n",
-        format!("CODE_HASH_{}", name.to_uppercase().replace("-", "_")),
-        hash
-    )
-        .expect("write to code_hashes.rs");
+fn parse_hash(hex: &str) -> [u8; 32] {
+    let hex = hex.trim_start_matches("0x");
+    assert_eq!(hex.len(), 64, "type id hash should be 32 bytes: {}", hex);
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).expect("parse type id hash");
+    }
+    hash
 }
 
 pub fn new_blake2b() -> Blake2b {
@@ -8,28 +8,36 @@
 use core::result::Result;
 
 mod code_hashes;
-use code_hashes::{CODE_HASH_DEPOSIT_LOCK, CODE_HASH_ANYONE_CAN_SPEND, CODE_HASH_AUDIT_DELAY};
+use code_hashes::{
+    CODE_HASH_DEPOSIT_LOCK, CODE_HASH_AUDIT_DELAY, CODE_HASH_BRIDGE_GUARD,
+    HASH_TYPE_DEPOSIT_LOCK, HASH_TYPE_AUDIT_DELAY, HASH_TYPE_BRIDGE_GUARD,
+};
 
 // Import heap related library from `alloc`
 // https://doc.rust-lang.org/alloc/index.html
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 
 use ckb_std::{
     debug,
     ckb_constants::Source,
-    ckb_types::bytes::Bytes,
+    ckb_types::{bytes::Bytes, packed::Script, prelude::*},
     default_alloc, entry,
     error::SysError,
     high_level::{
-        load_cell_data, load_cell_lock, load_cell_type, load_cell_type_hash, load_input_out_point,
+        load_cell_data, load_cell_lock, load_cell_type, load_cell_type_hash,
         load_script, load_script_hash, QueryIter, load_transaction, load_cell_capacity, load_input,
-        load_cell_lock_hash,
+        load_cell_lock_hash, load_input_since, load_cell_occupied_capacity,
+        load_header,
     },
 };
 use core::convert::TryFrom;
 use core::convert::TryInto;
+use blake2b_rs::Blake2bBuilder;
 use elliptic_curve::sec1::ToEncodedPoint;
 use k256::ecdsa::{recoverable};
+use k256::{AffinePoint, EncodedPoint, FieldBytes, ProjectivePoint, Scalar};
+use elliptic_curve::sec1::FromEncodedPoint;
+use sha2::Sha256;
 use sha3::{Digest, Keccak256};
 
 entry!(entry);
@@ -47,6 +55,87 @@ fn entry() -> i8 {
 // TODO: this parameter should be moved to bridge args eventually
 const TIMEOUT: u64 = 100;
 const ADDRESS_LEN: usize = 20;
+// args consist of type id (32 bytes) + trustee (32 bytes) + min deposit (8 bytes) + config type hash (32 bytes)
+const VALIDATORS_OFFSET: usize = 104;
+// the validator set follows as a mode byte and the mode's data
+const VALIDATOR_MODE_LIST: u8 = 0;
+const VALIDATOR_MODE_MERKLE: u8 = 1;
+const VALIDATOR_MODE_WEIGHTED_LIST: u8 = 2;
+const VALIDATOR_MODE_WEIGHTED_MERKLE: u8 = 3;
+const VALIDATOR_MODE_SCHNORR: u8 = 4;
+// set in the mode byte, validators are blake160 CKB identities instead of keccak addresses
+const IDENTITY_CKB_FLAG: u8 = 0x80;
+// CKB identities sign the ckb hash of the message behind this prefix, like Neuron does
+const CKB_MESSAGE_PREFIX: &[u8] = b"Nervos Message:";
+// set in the mode byte, receipts are signed as EIP-712 typed data and a chain id (8 bytes)
+// precedes the mode's data
+const EIP712_FLAG: u8 = 0x40;
+const CHAIN_ID_LEN: usize = 8;
+// set in the mode byte, the bridge is split into shard cells of the same type script and
+// their count (1 byte) follows the chain id. Every shard cell's data starts with its index.
+const SHARDED_FLAG: u8 = 0x20;
+const EIP712_DOMAIN_TYPE: &[u8] = b"EIP712Domain(string name,string version,uint256 chainId)";
+const EIP712_DOMAIN_NAME: &[u8] = b"Nervos Bridge";
+const EIP712_DOMAIN_VERSION: &[u8] = b"1";
+const WITHDRAWAL_TYPE: &[u8] =
+    b"Withdrawal(bytes32 bridgeId,bytes32 txHash,bytes32 receiver,uint256 amount,uint256 chainId,uint256 nonce,uint256 expiry,uint256 fee,bytes32 asset)";
+// x-only aggregate key and BIP340 signature
+const SCHNORR_KEY_LEN: usize = 32;
+const SCHNORR_SIGNATURE_LEN: usize = 64;
+// order of the secp256k1 group
+const CURVE_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+// ECDSA signatures need s <= order / 2 so every approval has a single encoding
+const HALF_CURVE_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+// Bridge.sol signs with v = 27 / 28, k256 uses the recovery id 0 / 1
+const ETHEREUM_V_OFFSET: u8 = 27;
+const WEIGHT_LEN: usize = 8;
+// merkle root (32 bytes) + signer threshold (8 bytes)
+const MERKLE_ARGS_LEN: usize = 40;
+const SIGNATURE_LEN: usize = 65;
+const RECEIPT_LEN: usize = 128;
+// receipt asset (32 bytes) at 0..32, the type hash of a token or zero for CKBytes
+const ASSET_OFFSET: usize = 0;
+const CKB_ASSET: Hash = [0u8; 32];
+// receipt nonce (8 bytes) at 32..40
+const NONCE_OFFSET: usize = 32;
+// receipt expiry (8 bytes, header timestamp in milliseconds, 0 never expires) at 40..48
+const EXPIRY_OFFSET: usize = 40;
+// relayer fee (8 bytes) at 48..56, kept by whoever submits the payout
+const FEE_OFFSET: usize = 48;
+// bridge data is total deposited, total withdrawn and payout count (8 bytes each),
+// then the nonce high-water mark (8 bytes) + a bitmap of the nonces below it
+const COUNTERS_LEN: usize = 24;
+const NONCE_WINDOW: u64 = 256;
+const CHECKPOINT_LEN: usize = 8 + NONCE_WINDOW as usize / 8;
+const BRIDGE_DATA_LEN: usize = COUNTERS_LEN + CHECKPOINT_LEN;
+// the asset registry follows, asset type hash (32 bytes) + balance (16 bytes) per token
+const REGISTRY_ENTRY_LEN: usize = 48;
+// asset cells and token payouts start their data with the sUDT amount (16 bytes, little endian)
+const UDT_AMOUNT_LEN: usize = 16;
+// config cell data is code hash (32 bytes) + hash type (1 byte) of deposit lock, audit delay and bridge guard
+const SCRIPT_ID_LEN: usize = 33;
+const CONFIG_DATA_LEN: usize = 3 * SCRIPT_ID_LEN;
+// the config cell's type has to be a Type ID, the only way to make a type hash unique
+const TYPE_ID_CODE_HASH: Hash = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, b'T', b'Y', b'P', b'E', b'_', b'I', b'D',
+];
+// audit delay args are trustee (32 bytes) + owner (32 bytes) + timeout (8 bytes) + bridge type hash (32 bytes)
+const AUDIT_DELAY_ARGS_LEN: usize = 104;
+// script hash type of scripts referenced by the type hash of their code cell
+const HASH_TYPE_TYPE: u8 = 1;
+const CKB_HASH_PERSONALIZATION: &[u8] = b"ckb-default-hash";
+// our args carry no version byte and count as version 0, a migration target's args
+// start with a higher version
+const ARGS_VERSION: u8 = 0;
+// migration witness is action byte + announcement delay (8 bytes) + approvals
+const MIGRATION_SIGS_OFFSET: usize = 9;
 
 /// Error
 #[repr(i8)]
@@ -80,6 +169,40 @@ enum Error {
     WrongPayoutDestination = 26,
     WrongTimeout = 27,
     ReceiptAlreadyUsed = 28,
+    NoDepositsCollected = 29,
+    DepositBelowMinimum = 30,
+    WrongBridgeInPayout = 31,
+    WrongPayoutData = 32,
+    NoVetoedPayouts = 33,
+    ReturnedCapacityComputedIncorrectly = 34,
+    UnknownVetoedReceipt = 35,
+    ConfigCellNotFound = 36,
+    InvalidConfigData = 37,
+    TooManyTypeInputs = 38,
+    NoMigrationTarget = 39,
+    WrongMigrationVersion = 40,
+    MigratedCapacityComputedIncorrectly = 41,
+    WrongReceiptCommitment = 42,
+    MigrationDelayNotPassed = 43,
+    InvalidAggregateSignature = 44,
+    HighSSignature = 45,
+    InvalidRecoveryId = 46,
+    BridgeCapacityTooLow = 47,
+    NonceBelowWindow = 48,
+    InvalidBridgeData = 49,
+    ReceiptExpired = 50,
+    ExpiryNotProven = 51,
+    FeeExceedsAmount = 52,
+    UnknownAsset = 53,
+    InsufficientAssetBalance = 54,
+    WrongPayoutAsset = 55,
+    AssetCellsComputedIncorrectly = 56,
+    WrongShard = 57,
+    NothingToRebalance = 58,
+    RebalancedCapacityComputedIncorrectly = 59,
+    ConfigNotTypeId = 60,
+    NonceOutOfRange = 61,
+    MigrationWithTokens = 62,
 }
 
 impl From<SysError> for Error {
@@ -98,14 +221,73 @@ impl From<SysError> for Error {
 type Address = [u8; ADDRESS_LEN];
 type Hash = [u8;32];
 type Receipt = [u8; 128];
-type Signature = [u8; 65];
+
+/// How validators derive their identity from their key and what they sign
+#[derive(Clone, Copy, PartialEq)]
+enum IdentityScheme {
+    /// keccak address, signing like `personal_sign`
+    Ethereum,
+    /// blake160 of the compressed key, signing like `ckb-cli` and Neuron
+    Ckb,
+    /// keccak address, receipts are signed as an EIP-712 `Withdrawal` of this bridge
+    Eip712 { bridge_id: Hash, chain_id: u64 },
+}
+
+/// The validators of the bridge and the identity scheme they sign with
+struct Validators {
+    scheme: IdentityScheme,
+    set: ValidatorSet,
+}
+
+/// The validators of the bridge, stored in the args according to the mode byte
+enum ValidatorSet {
+    /// flat list of addresses, 2/3 of which have to sign
+    List(Vec<Address>),
+    /// addresses with stake weights, the weight of the signers has to reach the threshold
+    WeightedList { validators: Vec<(Address, u64)>, threshold: u64 },
+    /// keccak merkle root over the addresses (and weights), signers prove their membership
+    /// in the witness
+    MerkleRoot { root: Hash, threshold: u64, weighted: bool },
+    /// x-only key the validators aggregated (MuSig, FROST), one BIP340 signature approves
+    AggregateKey(Hash),
+}
+
+/// Code hash and hash type of a helper script
+struct ScriptId {
+    code_hash: Hash,
+    hash_type: u8,
+}
+
+/// The helper scripts the bridge accepts, compiled in or read from the config cell
+struct HelperScripts {
+    deposit_lock: ScriptId,
+    audit_delay: ScriptId,
+    bridge_guard: ScriptId,
+}
+
+/// The shard cell a transition spends, an unsharded bridge is its only shard
+#[derive(Clone, Copy)]
+struct Shard {
+    index: u8,
+    count: u8,
+}
 
 enum StateTransition {
-    DeployBridge { validators: Vec<Address>, id: Bytes , trustee: Hash},
+    DeployBridge {
+        validator_args: Bytes,
+        id: Bytes,
+        trustee: Hash,
+        min_deposit: u64,
+        config: Hash,
+        helpers: HelperScripts,
+        shards: u8,
+    },
     Payout {
-        validators: Vec<Address>,
+        helpers: HelperScripts,
+        validators: Validators,
+        shard: Shard,
         receipt: Receipt,
-        sigs: Vec<Signature>,
+        approvals: Bytes,
         cap_before: u64,
         cap_after: u64,
         data_before: Vec<u8>,
@@ -113,13 +295,226 @@ enum StateTransition {
         trustee: [u8; 32],
     },
     CollectDeposits {
-        total: u64,
+        helpers: HelperScripts,
+        deposits: Vec<Deposit>,
+        min_deposit: u64,
         cap_before: u64,
         cap_after: u64,
         data_before: Vec<u8>,
         data_after: Vec<u8>,
+        trustee: Hash,
     },
     HaltAndDissolve { trustee: Hash},
+    ReturnVetoedPayout {
+        helpers: HelperScripts,
+        shard: Shard,
+        vetoed: Vec<VetoedPayout>,
+        // the receipts of the vetoed payouts, in the same order
+        receipts: Bytes,
+        cap_before: u64,
+        cap_after: u64,
+        data_before: Vec<u8>,
+        data_after: Vec<u8>,
+        trustee: Hash,
+    },
+    Migrate {
+        validators: Validators,
+        approvals: Bytes,
+        delay: u64,
+        cap_before: u64,
+        cap_after: u64,
+        data_before: Vec<u8>,
+        data_after: Vec<u8>,
+        trustee: Hash,
+        shards: u8,
+    },
+    Rebalance {
+        helpers: HelperScripts,
+        validators: Validators,
+        approvals: Bytes,
+    },
+}
+
+/// A deposit lock cell of this bridge, CKBytes or a token if it has a type script
+struct Deposit {
+    asset: Option<Hash>,
+    capacity: u64,
+    data: Vec<u8>,
+}
+
+/// An audit delay cell of this bridge, spent by the trustee to return it to the bridge
+struct VetoedPayout {
+    trustee: Bytes,
+    receipt_hash: Vec<u8>,
+    capacity: u64,
+    // token payouts carry the type script of the asset and a sUDT amount
+    asset: Option<Hash>,
+    amount: u128,
+}
+
+/// Cumulative accounting of the bridge, so auditors can compare it with the childchain
+/// supply, followed by the used receipt nonces and the registry of the tokens the bridge
+/// holds in its asset cells.
+#[derive(Clone)]
+struct BridgeData {
+    total_deposited: u64,
+    total_withdrawn: u64,
+    payout_count: u64,
+    checkpoint: Checkpoint,
+    registry: Vec<(Hash, u128)>,
+}
+
+impl BridgeData {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        // a new bridge has not collected or paid out anything yet
+        if data.len() == 0 {
+            return Ok(BridgeData {
+                total_deposited: 0,
+                total_withdrawn: 0,
+                payout_count: 0,
+                checkpoint: Checkpoint { high_water_mark: 0, window: [0u8; 32] },
+                registry: Vec::new(),
+            });
+        }
+        if data.len() < BRIDGE_DATA_LEN || (data.len() - BRIDGE_DATA_LEN) % REGISTRY_ENTRY_LEN != 0 {
+            return Err(Error::InvalidBridgeData);
+        }
+        let registry = data[BRIDGE_DATA_LEN..]
+            .chunks(REGISTRY_ENTRY_LEN)
+            .map(|entry| {
+                let mut balance = [0u8; 16];
+                balance.copy_from_slice(&entry[32..]);
+                (slice_to_array_32(&entry[..32]), u128::from_be_bytes(balance))
+            })
+            .collect();
+        Ok(BridgeData {
+            total_deposited: parse_u64(&data[0..8]),
+            total_withdrawn: parse_u64(&data[8..16]),
+            payout_count: parse_u64(&data[16..24]),
+            checkpoint: Checkpoint::parse(&data[COUNTERS_LEN..BRIDGE_DATA_LEN])?,
+            registry: registry,
+        })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = [
+            &self.total_deposited.to_be_bytes()[..],
+            &self.total_withdrawn.to_be_bytes()[..],
+            &self.payout_count.to_be_bytes()[..],
+            &self.checkpoint.serialize()[..],
+        ]
+        .concat();
+        for (asset, balance) in self.registry.iter() {
+            data.extend_from_slice(&asset[..]);
+            data.extend_from_slice(&balance.to_be_bytes());
+        }
+        data
+    }
+
+    fn balance(&self, asset: &Hash) -> u128 {
+        self.registry
+            .iter()
+            .find(|(registered, _)| registered == asset)
+            .map_or(0, |(_, balance)| *balance)
+    }
+
+    fn balance_mut(&mut self, asset: &Hash) -> Option<&mut u128> {
+        self.registry
+            .iter_mut()
+            .find(|(registered, _)| registered == asset)
+            .map(|(_, balance)| balance)
+    }
+
+    /// Adds a token deposit, registering the asset the first time it is deposited.
+    fn deposit_asset(&mut self, asset: Hash, amount: u128) -> Result<(), Error> {
+        if let Some(balance) = self.balance_mut(&asset) {
+            *balance = balance.checked_add(amount).ok_or(Error::InvalidBridgeData)?;
+            return Ok(());
+        }
+        self.registry.push((asset, amount));
+        Ok(())
+    }
+}
+
+/// The used receipt nonces: one past the highest nonce paid out and a window where bit i
+/// marks nonce `high_water_mark - 1 - i` as used. Nonces below the window count as used,
+/// so the bridge data keeps its size however many payouts there are.
+#[derive(Clone)]
+struct Checkpoint {
+    high_water_mark: u64,
+    window: [u8; 32],
+}
+
+impl Checkpoint {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != CHECKPOINT_LEN {
+            return Err(Error::InvalidBridgeData);
+        }
+        Ok(Checkpoint {
+            high_water_mark: parse_u64(&data[..8]),
+            window: slice_to_array_32(&data[8..]),
+        })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        [&self.high_water_mark.to_be_bytes()[..], &self.window[..]].concat()
+    }
+
+    /// Position of the nonce in the window, none if it is above the high-water mark
+    /// or has fallen out of the window.
+    fn position(&self, nonce: u64) -> Option<usize> {
+        if nonce >= self.high_water_mark || self.high_water_mark - nonce > NONCE_WINDOW {
+            return None;
+        }
+        Some((self.high_water_mark - 1 - nonce) as usize)
+    }
+
+    fn is_used(&self, position: usize) -> bool {
+        self.window[position / 8] & (1 << (position % 8)) != 0
+    }
+
+    fn mark_used(&mut self, nonce: u64) -> Result<(), Error> {
+        if nonce >= self.high_water_mark {
+            let high_water_mark = nonce.checked_add(1).ok_or(Error::NonceOutOfRange)?;
+            self.slide(high_water_mark - self.high_water_mark);
+            self.high_water_mark = high_water_mark;
+        }
+        let position = self.position(nonce).ok_or(Error::NonceBelowWindow)?;
+        if self.is_used(position) {
+            return Err(Error::ReceiptAlreadyUsed);
+        }
+        self.window[position / 8] |= 1 << (position % 8);
+        Ok(())
+    }
+
+    /// Frees the nonce of a vetoed payout, so the receipt can be paid out again. Below
+    /// the window the nonce stays used and the validators have to sign a new receipt.
+    fn unmark(&mut self, nonce: u64) -> Result<(), Error> {
+        if nonce >= self.high_water_mark {
+            return Err(Error::UnknownVetoedReceipt);
+        }
+        let position = match self.position(nonce) {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        if !self.is_used(position) {
+            return Err(Error::UnknownVetoedReceipt);
+        }
+        self.window[position / 8] &= !(1 << (position % 8));
+        Ok(())
+    }
+
+    /// Moves the window up by `distance` nonces, the oldest ones drop out.
+    fn slide(&mut self, distance: u64) {
+        let mut window = [0u8; 32];
+        for position in distance..NONCE_WINDOW {
+            let old = (position - distance) as usize;
+            if self.is_used(old) {
+                window[position as usize / 8] |= 1 << (position % 8);
+            }
+        }
+        self.window = window;
+    }
 }
 
 impl StateTransition {
@@ -133,23 +528,30 @@ impl StateTransition {
         }
 
         let script_args: Bytes = load_script()?.args().raw_data();
-        let validators = parse_validator_list_from_args(&*script_args)?;
-        if validators.len() == 0 {
-            return Err(Error::EmptyValidatorList);
-        };
-        let state_id: Bytes = get_state_id()?;
-        debug!("validators: {:?}", validators);
+        if script_args.len() <= VALIDATORS_OFFSET {
+            return Err(Error::WrongScriptArgsLength);
+        }
+        let validators = parse_validator_set_from_args(&*script_args)?;
         let trustee = parse_trustee_from_args(&*script_args)?;
+        let min_deposit = parse_min_deposit_from_args(&*script_args)?;
+        let config = parse_config_from_args(&*script_args)?;
+        let helpers = load_helper_scripts(&config)?;
+        let shards = parse_shard_count_from_args(&*script_args)?;
 
-        // check state ID
-        only_one_output_has_state_id()?;
+        // like Type ID, there is at most one bridge cell per shard on either side
+        at_most_one_input_per_shard(shards)?;
+        at_most_one_output_per_shard(shards)?;
 
         let isd = is_deploy()?;
         if isd {
             return Ok(StateTransition::DeployBridge {
-                validators: validators,
-                id: state_id,
+                validator_args: script_args.slice(VALIDATORS_OFFSET..),
+                id: calc_type_id()?,
                 trustee: trustee,
+                min_deposit: min_deposit,
+                config: config,
+                helpers: helpers,
+                shards: shards,
             });
         }
 
@@ -159,37 +561,36 @@ impl StateTransition {
 
         // read action byte
         let action_byte: u8 = (*witness.get_unchecked(0).as_slice())[0];
-        let bridge_cap_before = load_cell_capacity(0, Source::Input)?;
-        let bridge_cap_after = load_cell_capacity(0, Source::Output)?;
-        let data_before = load_cell_data(0, Source::Input)?;
-        let data_after = load_cell_data(0, Source::Output)?;
+        // the bridge cell is the one in this script group, whatever sits at index 0. The
+        // migration target takes output 0, a dissolved bridge has no output left.
+        let output_source = match action_byte {
+            2 | 4 => Source::Output,
+            _ => Source::GroupOutput,
+        };
+        let bridge_cap_before = load_cell_capacity(0, Source::GroupInput)?;
+        let bridge_cap_after = load_cell_capacity(0, output_source)?;
+        let data_before = load_cell_data(0, Source::GroupInput)?;
+        let data_after = load_cell_data(0, output_source)?;
 
         // distinguished based on first byte of witness
         match action_byte {
             // prepare and call payout
             0 => {
-                //check for correct Encoding of Witness
-                if witness.len() >= 194 && (witness.len() - 129) % 65 != 0 {
+                //check for correct Encoding of Witness, approvals are checked with the validator set
+                if witness.len() < 1 + RECEIPT_LEN {
                     return Err(Error::InvalidWitnessEncoding);
                 }
                 // make receipt our own 💪
                 let mut receipt: [u8; 128] = [0u8; 128];
-                receipt.copy_from_slice(&witness.raw_data().slice(1..129));
-                let mut sigs = Vec::new();
-                //calculate vector length of signatures
-                let signatures_vector_length = (witness.len() - 129) / 65;
-
-                for x in 0..signatures_vector_length {
-                    let mut temp_sig: [u8; 65] = [0u8; 65];
-                    temp_sig.copy_from_slice(
-                        &witness.raw_data().slice(129 + x * 65..129 + (x + 1) * 65),
-                    );
-                    sigs.push(temp_sig);
-                }
+                receipt.copy_from_slice(&witness.raw_data().slice(1..1 + RECEIPT_LEN));
+                only_one_shard()?;
+                let (shard, data_before, data_after) = split_shard(data_before, data_after, shards)?;
                 Ok(StateTransition::Payout {
+                    helpers: helpers,
                     validators: validators,
+                    shard: shard,
                     receipt: receipt,
-                    sigs: sigs,
+                    approvals: witness.raw_data().slice(1 + RECEIPT_LEN..),
                     cap_before: bridge_cap_before,
                     cap_after: bridge_cap_after,
                     data_before: data_before,
@@ -199,19 +600,33 @@ impl StateTransition {
             }
             // prepare and call "collect deposits"
             1 => {
-                let total_deposit_capacity = QueryIter::new(load_cell_lock, Source::Input)
+                only_one_shard()?;
+                let (_, data_before, data_after) = split_shard(data_before, data_after, shards)?;
+                // only deposits that name this bridge, not a cell that merely sits at input 0
+                let my_hash = load_script_hash()?;
+                let deposits = QueryIter::new(load_cell_lock, Source::Input)
+                    .zip(QueryIter::new(load_cell_type_hash, Source::Input))
                     .zip(QueryIter::new(load_cell_capacity, Source::Input))
-                    .filter(|(script, _)| {
-                        *script.code_hash().raw_data() == CODE_HASH_DEPOSIT_LOCK[..]
+                    .zip(QueryIter::new(load_cell_data, Source::Input))
+                    .filter(|(((script, _), _), _)| {
+                        is_script(script, &helpers.deposit_lock)
+                            && script.args().raw_data().get(32..64) == Some(&my_hash[..])
                     })
-                    .map(|(_, cap)| cap)
-                    .sum();
+                    .map(|(((_, asset), cap), data)| Deposit {
+                        asset: asset,
+                        capacity: cap,
+                        data: data,
+                    })
+                    .collect();
                 Ok(Self::CollectDeposits {
-                    total: total_deposit_capacity,
+                    helpers: helpers,
+                    deposits: deposits,
+                    min_deposit: min_deposit,
                     cap_before: bridge_cap_before,
                     cap_after: bridge_cap_after,
                     data_before: data_before,
                     data_after: data_after,
+                    trustee: trustee,
                 })
             },
             2 => {
@@ -219,101 +634,193 @@ impl StateTransition {
                     trustee: trustee,
                 })
             },
+            // prepare and call "return vetoed payout"
+            3 => {
+                only_one_shard()?;
+                let (shard, data_before, data_after) = split_shard(data_before, data_after, shards)?;
+                let my_hash = load_script_hash()?;
+                let vetoed = QueryIter::new(load_cell_lock, Source::Input)
+                    .zip(QueryIter::new(load_cell_type_hash, Source::Input))
+                    .zip(QueryIter::new(load_cell_capacity, Source::Input))
+                    .zip(QueryIter::new(load_cell_data, Source::Input))
+                    .filter(|(((script, _), _), _)| {
+                        let args = script.args().raw_data();
+                        is_script(script, &helpers.audit_delay)
+                            && args.len() == AUDIT_DELAY_ARGS_LEN
+                            && *args.slice(72..104) == my_hash[..]
+                    })
+                    .map(|(((script, asset), cap), data)| {
+                        // token payouts keep the sUDT amount in front of the receipt hash
+                        let offset = if asset.is_some() { UDT_AMOUNT_LEN } else { 0 };
+                        VetoedPayout {
+                            trustee: script.args().raw_data().slice(0..32),
+                            // a frozen or released payout carries a status byte after the hash
+                            receipt_hash: data.iter().skip(offset).take(32).copied().collect(),
+                            capacity: cap,
+                            asset: asset,
+                            amount: udt_amount(&data).unwrap_or(0),
+                        }
+                    })
+                    .collect();
+                Ok(StateTransition::ReturnVetoedPayout {
+                    helpers: helpers,
+                    shard: shard,
+                    vetoed: vetoed,
+                    receipts: witness.raw_data().slice(1..),
+                    cap_before: bridge_cap_before,
+                    cap_after: bridge_cap_after,
+                    data_before: data_before,
+                    data_after: data_after,
+                    trustee: trustee,
+                })
+            },
+            // prepare and call "migrate"
+            4 => {
+                // every shard migrates on its own
+                only_one_shard()?;
+                let raw_witness = witness.raw_data();
+                if raw_witness.len() < MIGRATION_SIGS_OFFSET {
+                    return Err(Error::InvalidWitnessEncoding);
+                }
+                let delay_array: [u8; 8] = (&raw_witness[1..MIGRATION_SIGS_OFFSET])
+                    .try_into()
+                    .map_err(|_| Error::InvalidWitnessEncoding)?;
+                Ok(StateTransition::Migrate {
+                    validators: validators,
+                    approvals: raw_witness.slice(MIGRATION_SIGS_OFFSET..),
+                    delay: u64::from_be_bytes(delay_array),
+                    cap_before: bridge_cap_before,
+                    cap_after: bridge_cap_after,
+                    data_before: data_before,
+                    data_after: data_after,
+                    trustee: trustee,
+                    shards: shards,
+                })
+            },
+            // prepare and call "rebalance"
+            5 => {
+                if shards == 1 {
+                    return Err(Error::StateTransitionDoesNotExist);
+                }
+                Ok(StateTransition::Rebalance {
+                    helpers: helpers,
+                    validators: validators,
+                    approvals: witness.raw_data().slice(1..),
+                })
+            },
             _ => Err(Error::StateTransitionDoesNotExist),
         }
     }
 
     fn verify(&self) -> Result<(), Error> {
         match self {
-            Self::DeployBridge { validators, id ,trustee} => {
-                // lock script on output0 should be anyone can spend
-                let lock_code_hash = load_cell_lock(0, Source::Output)?.code_hash().raw_data();
-                if *lock_code_hash != CODE_HASH_ANYONE_CAN_SPEND[..] {
+            Self::DeployBridge { validator_args, id, trustee, min_deposit, config, helpers, shards } => {
+                // lock script on output0 should be the bridge guard of this bridge
+                let script_hash = load_script_hash()?;
+                let lock = load_cell_lock(0, Source::Output)?;
+                if !is_script(&lock, &helpers.bridge_guard) {
+                    return Err(Error::WrongLockScript);
+                }
+                if *lock.args().raw_data() != [&script_hash[..], &trustee[..]].concat()[..] {
                     return Err(Error::WrongLockScript);
                 }
                 // type script on output0 should be our script
                 let type_script_hash = load_cell_type_hash(0, Source::Output)?.unwrap();
-                let script_hash = load_script_hash()?;
 
                 if type_script_hash != script_hash {
                     return Err(Error::WrongTypeScript);
                 }
-                // data on output0 should be nothing
-                let data = load_cell_data(0, Source::Output)?;
+                if *shards == 1 {
+                    // data on output0 should be nothing
+                    let data = load_cell_data(0, Source::Output)?;
 
-                if data.len() != 0 {
-                    return Err(Error::DataLengthNotZero);
+                    if data.len() != 0 {
+                        return Err(Error::DataLengthNotZero);
+                    }
+                } else {
+                    // all shards are created at once, in the order of their index
+                    if QueryIter::new(load_cell_lock_hash, Source::GroupOutput).count() != *shards as usize {
+                        return Err(Error::WrongShard);
+                    }
+                    let lock_hash = load_cell_lock_hash(0, Source::Output)?;
+                    for (index, data) in QueryIter::new(load_cell_data, Source::GroupOutput).enumerate() {
+                        if load_cell_lock_hash(index, Source::GroupOutput)? != lock_hash {
+                            return Err(Error::WrongLockScript);
+                        }
+                        if data[..] != [index as u8][..] {
+                            return Err(Error::WrongShard);
+                        }
+                    }
                 }
 
-                // verify typescript args contains id and trustee and validators
+                // verify typescript args contains id, trustee, min deposit, config and validators
                 let type_script_0 = load_cell_type(0, Source::Output)?.unwrap();
                 let type_script_args = type_script_0.args().raw_data();
-                let validators_flat = Bytes::from(validators[..].concat());
-                let id_and_trustee_and_validators = Bytes::from([&*id, &*Bytes::from(&trustee[..]), &*validators_flat].concat());
+                let expected_args = Bytes::from([&id[..], &trustee[..], &min_deposit.to_be_bytes()[..], &config[..], &validator_args[..]].concat());
 
-                if id_and_trustee_and_validators != type_script_args {
+                if expected_args != type_script_args {
                     return Err(Error::WrongStateId);
                 }
 
                 Ok(())
             }
             Self::Payout {
+                helpers,
                 validators,
+                shard,
                 receipt,
-                sigs,
+                approvals,
                 cap_before,
                 cap_after,
                 data_before,
                 data_after,
                 trustee,
             } => {
+                verify_lock_unchanged()?;
                 let hash = Keccak256::digest(&receipt[..]);
-                let mut quorum = Vec::new();
-                // init verification vector
-                for i in 0..(validators.len()) {
-                    quorum.push(false);
-                }
-                // recover all signers
-                for i in 0..(sigs.len()) {
-                    let preamble: &[u8] = b"\x19Ethereum Signed Message:\n128";
-                    let sig: recoverable::Signature =
-                        recoverable::Signature::try_from(&sigs[i][..]).unwrap();
-                    let recovered_key = sig.recover_verify_key([preamble, &receipt[..]].concat().as_slice()).unwrap();
-                    let mut addr: [u8; 20] = [0u8; 20];
-                    addr.copy_from_slice(
-                        &Keccak256::digest(&recovered_key.to_encoded_point(false).as_bytes()[1..65])[12..],
-                    );
-                    let pos = get_position(addr, validators)?;
-                    quorum[pos] = true;
-                }
-                // determine quorum
-                let mut sig_count = 0;
-                for i in 0..(validators.len()) {
-                    if quorum[i] {
-                        sig_count += 1;
-                    }
-                }
-                if sig_count < validators.len() * 2 / 3 {
-                    return Err(Error::SignatureQuorumNotMet);
-                }
-                // check capacity
+                let digest = receipt_digest(receipt, validators.scheme);
+                verify_quorum(&receipt[..], &digest, approvals, validators)?;
+                verify_not_expired(receipt_expiry(receipt))?;
+                // check capacity, tokens come from the asset cells and leave it as it is
                 let mut amount_array: [u8; 8] = [0u8; 8];
                 amount_array.copy_from_slice(&receipt[56..64]);
                 let amount = u64::from_be_bytes(amount_array);
-                if *cap_after != cap_before - amount {
+                let asset = receipt_asset(receipt);
+                let withdrawn_cap = if asset == CKB_ASSET { amount } else { 0 };
+                if cap_before.checked_sub(withdrawn_cap) != Some(*cap_after) {
                     return Err(Error::WithdrawalCapacityComputedIncorrectly);
                 }
-                // check payout output
-                let payout_cap = load_cell_capacity(1, Source::Output)?;
-                if payout_cap != amount {
-                    return Err(Error::InvalidWithdrawalCapacity);
-                }
-                let lock_code_hash = load_cell_lock(1, Source::Output)?.code_hash().raw_data();
-                if *lock_code_hash != CODE_HASH_AUDIT_DELAY[..] {
+                verify_bridge_capacity(0, Source::GroupOutput, *cap_after)?;
+                // check payout output, the fee is left to the submitter
+                let payout_amount = amount
+                    .checked_sub(receipt_fee(receipt))
+                    .ok_or(Error::FeeExceedsAmount)?;
+                let payout_data = load_cell_data(1, Source::Output)?;
+                let payout_type = load_cell_type_hash(1, Source::Output)?;
+                let receipt_hash_offset = if asset == CKB_ASSET {
+                    // audit delay tells token payouts apart by their type script
+                    if payout_type.is_some() {
+                        return Err(Error::WrongPayoutAsset);
+                    }
+                    if load_cell_capacity(1, Source::Output)? != payout_amount {
+                        return Err(Error::InvalidWithdrawalCapacity);
+                    }
+                    0
+                } else {
+                    if payout_type != Some(asset) {
+                        return Err(Error::WrongPayoutAsset);
+                    }
+                    if udt_amount(&payout_data) != Some(payout_amount as u128) {
+                        return Err(Error::InvalidWithdrawalCapacity);
+                    }
+                    UDT_AMOUNT_LEN
+                };
+                let lock = load_cell_lock(1, Source::Output)?;
+                if !is_script(&lock, &helpers.audit_delay) {
                     return Err(Error::WrongLockScript);
                 }
-                let lock_args = load_cell_lock(1, Source::Output)?.args().raw_data();
-                if lock_args.len() != 72 {
+                let lock_args = lock.args().raw_data();
+                if lock_args.len() != AUDIT_DELAY_ARGS_LEN {
                     return Err(Error::WrongScriptArgsLength);
                 }
                 let trustee_lock_hash = lock_args.slice(0..32);
@@ -329,55 +836,103 @@ impl StateTransition {
                 if timeout != TIMEOUT {
                     return Err(Error::WrongTimeout);
                 }
-
-                let expected_data = [data_before, &hash[..]].concat();
-                if data_after != &expected_data {
-                    return Err(Error::DataUpdatedIncorrectly);
+                // vetoed payouts can only be returned to this bridge
+                let bridge_type_hash = lock_args.slice(72..104);
+                if *bridge_type_hash != load_script_hash()?[..] {
+                    return Err(Error::WrongBridgeInPayout);
+                }
+                // the payout remembers its receipt, so a veto can un-mark it
+                if payout_data.get(receipt_hash_offset..) != Some(&hash[..]) {
+                    return Err(Error::WrongPayoutData);
                 }
 
-                let used_hashes = parse_data(data_before);
-                if used_hashes.iter().any(|h| h == &hash[..].to_vec()) {
-                    return Err(Error::ReceiptAlreadyUsed);
+                // receipts are marked used by their nonce, the counters include the fee
+                let data_before = BridgeData::parse(data_before)?;
+                let mut bridge_data = data_before.clone();
+                bridge_data.checkpoint.mark_used(shard_nonce(receipt, shard)?)?;
+                if asset == CKB_ASSET {
+                    bridge_data.total_withdrawn = bridge_data
+                        .total_withdrawn
+                        .checked_add(amount)
+                        .ok_or(Error::InvalidBridgeData)?;
+                } else {
+                    let balance = bridge_data.balance_mut(&asset).ok_or(Error::UnknownAsset)?;
+                    *balance = balance
+                        .checked_sub(amount as u128)
+                        .ok_or(Error::InsufficientAssetBalance)?;
                 }
-                
-                Ok(())
+                bridge_data.payout_count = bridge_data
+                    .payout_count
+                    .checked_add(1)
+                    .ok_or(Error::InvalidBridgeData)?;
+                if data_after != &bridge_data.serialize() {
+                    return Err(Error::DataUpdatedIncorrectly);
+                }
+                verify_asset_cells(helpers, &data_before, &bridge_data, 0)
             }
             Self::CollectDeposits {
-                total,
+                helpers,
+                deposits,
+                min_deposit,
                 cap_before,
                 cap_after,
                 data_before,
                 data_after,
+                trustee,
             } => {
                 verify_state_id()?;
+                verify_lock_unchanged()?;
+                // sweeping nothing or dust would only churn the bridge cell
+                if deposits.len() == 0 {
+                    return Err(Error::NoDepositsCollected);
+                }
+                let data_before = BridgeData::parse(data_before)?;
+                let mut bridge_data = data_before.clone();
+                let mut total: u64 = 0;
+                let mut token_capacity: u64 = 0;
+                for deposit in deposits.iter() {
+                    match deposit.asset {
+                        Some(asset) => {
+                            // the minimum is in shannons and says nothing about a token amount, whose
+                            // decimals differ per token, so a token deposit only has to carry some tokens.
+                            // Its capacity moves into the asset cells with it.
+                            let amount = udt_amount(&deposit.data)
+                                .filter(|amount| *amount > 0)
+                                .ok_or(Error::DepositBelowMinimum)?;
+                            bridge_data.deposit_asset(asset, amount)?;
+                            token_capacity += deposit.capacity;
+                        }
+                        None => {
+                            if deposit.capacity < *min_deposit {
+                                return Err(Error::DepositBelowMinimum);
+                            }
+                            total += deposit.capacity;
+                        }
+                    }
+                }
                 if *cap_after != total + cap_before {
                     return Err(Error::DepositCapacityComputedIncorrectly);
                 }
-                if data_before != data_after {
+                // registering a token grows the bridge data, the trustee has to agree
+                if bridge_data.registry.len() > data_before.registry.len() {
+                    verify_trustee_signed(trustee)?;
+                    verify_bridge_capacity(0, Source::GroupOutput, *cap_after)?;
+                }
+                // only the deposit total and the token balances change
+                bridge_data.total_deposited = bridge_data
+                    .total_deposited
+                    .checked_add(total)
+                    .ok_or(Error::InvalidBridgeData)?;
+                if data_after != &bridge_data.serialize() {
                     return Err(Error::DepositsShouldNotChangeData);
                 }
-                Ok(())
+                verify_asset_cells(helpers, &data_before, &bridge_data, token_capacity)
             }
             Self::HaltAndDissolve {trustee} => {
-                //Is trustee signer of any input?
-                let trustee_signed = QueryIter::new(load_cell_lock_hash, Source::Input)
-                    .filter(|hash| hash == trustee)
-                    .count()
-                    > 0;
-                if !trustee_signed {
-                    return Err(Error::NotSignedByTrustee);
-                }
-                //Check there is no bridge in the outputs
-                let my_hash = load_script_hash()?;
-                let is_bridge_in_outputs = QueryIter::new(load_cell_type_hash, Source::Output)
-                    .filter(|option| option.map_or(false, |hash| hash == my_hash))
-                    .count()
-                    > 0;
-                if is_bridge_in_outputs {
-                    return Err(Error::BridgeWasNotDissolved);
-                }
-                //Check all capacity is spent
-                let bridge_cap = load_cell_capacity(0, Source::GroupInput)?;
+                verify_trustee_signed(trustee)?;
+                verify_bridge_not_in_outputs()?;
+                //Check all capacity is spent, the trustee may dissolve several shards at once
+                let bridge_cap: u64 = QueryIter::new(load_cell_capacity, Source::GroupInput).sum();
                 let outputs_cap = QueryIter::new(load_cell_capacity, Source::Output)
                     .sum();
                 if bridge_cap > outputs_cap {
@@ -385,16 +940,580 @@ impl StateTransition {
                 }
                 Ok(())
             }
+            Self::ReturnVetoedPayout {
+                helpers,
+                shard,
+                vetoed,
+                receipts,
+                cap_before,
+                cap_after,
+                data_before,
+                data_after,
+                trustee,
+            } => {
+                verify_state_id()?;
+                verify_lock_unchanged()?;
+                // only the trustee vetoes, so only the trustee may return the payouts
+                verify_trustee_signed(trustee)?;
+                if vetoed.len() == 0 {
+                    return Err(Error::NoVetoedPayouts);
+                }
+                if receipts.len() != vetoed.len() * RECEIPT_LEN {
+                    return Err(Error::InvalidWitnessEncoding);
+                }
+                let mut returned_cap = 0;
+                let data_before = BridgeData::parse(data_before)?;
+                let mut bridge_data = data_before.clone();
+                for (payout, receipt) in vetoed.iter().zip(receipts.chunks(RECEIPT_LEN)) {
+                    if *payout.trustee != trustee[..] {
+                        return Err(Error::WrongTrusteeInPayout);
+                    }
+                    if Keccak256::digest(receipt)[..] != payout.receipt_hash[..] {
+                        return Err(Error::UnknownVetoedReceipt);
+                    }
+                    // un-mark the receipt of the vetoed payout
+                    bridge_data.checkpoint.unmark(shard_nonce(receipt, shard)?)?;
+                    let asset = receipt_asset(receipt);
+                    if payout.asset.unwrap_or(CKB_ASSET) != asset {
+                        return Err(Error::UnknownVetoedReceipt);
+                    }
+                    if asset == CKB_ASSET {
+                        returned_cap += payout.capacity;
+                    } else {
+                        // the tokens go back into the asset cells
+                        let balance = bridge_data.balance_mut(&asset).ok_or(Error::UnknownAsset)?;
+                        *balance = balance
+                            .checked_add(payout.amount)
+                            .ok_or(Error::InvalidBridgeData)?;
+                    }
+                }
+                if *cap_after != cap_before + returned_cap {
+                    return Err(Error::ReturnedCapacityComputedIncorrectly);
+                }
+                // the returned capacity was never withdrawn, the relayer fee was
+                bridge_data.total_withdrawn = bridge_data
+                    .total_withdrawn
+                    .checked_sub(returned_cap)
+                    .ok_or(Error::InvalidBridgeData)?;
+                if data_after != &bridge_data.serialize() {
+                    return Err(Error::DataUpdatedIncorrectly);
+                }
+                verify_asset_cells(helpers, &data_before, &bridge_data, 0)
+            }
+            Self::Migrate {
+                validators,
+                approvals,
+                delay,
+                cap_before,
+                cap_after,
+                data_before,
+                data_after,
+                trustee,
+                shards,
+            } => {
+                verify_trustee_signed(trustee)?;
+                verify_bridge_not_in_outputs()?;
+                // the new bridge on output0 has to run new code with newer args
+                let target = load_cell_type(0, Source::Output)?.ok_or(Error::NoMigrationTarget)?;
+                if target.code_hash().as_slice() == load_script()?.code_hash().as_slice() {
+                    return Err(Error::NoMigrationTarget);
+                }
+                let target_version = target.args().raw_data().get(0).copied().unwrap_or(ARGS_VERSION);
+                if target_version <= ARGS_VERSION {
+                    return Err(Error::WrongMigrationVersion);
+                }
+                // validators sign this bridge, the target, the lock it moves to and the
+                // announcement delay
+                let target_hash = load_cell_type_hash(0, Source::Output)?.ok_or(Error::NoMigrationTarget)?;
+                let target_lock_hash = load_cell_lock_hash(0, Source::Output)?;
+                let message = [
+                    &load_script_hash()?[..],
+                    &target_hash[..],
+                    &target_lock_hash[..],
+                    &delay.to_be_bytes()[..],
+                ]
+                .concat();
+                let digest = signing_digest(&message, validators.scheme);
+                verify_quorum(&message, &digest, approvals, validators)?;
+                // the bridge cell has to stay untouched for the delay the validators announced
+                if *delay != 0 && load_input_since(0, Source::GroupInput)? != *delay {
+                    return Err(Error::MigrationDelayNotPassed);
+                }
+                if cap_after != cap_before {
+                    return Err(Error::MigratedCapacityComputedIncorrectly);
+                }
+                // the asset cells stay guarded for this bridge, its tokens have to be paid out
+                // before it moves
+                let bridge_data = if *shards == 1 {
+                    &data_before[..]
+                } else {
+                    data_before.get(1..).ok_or(Error::WrongShard)?
+                };
+                if BridgeData::parse(bridge_data)?.registry.iter().any(|(_, balance)| *balance != 0) {
+                    return Err(Error::MigrationWithTokens);
+                }
+                // the used receipts move over as a single commitment
+                if data_after[..] != Keccak256::digest(&data_before[..])[..] {
+                    return Err(Error::WrongReceiptCommitment);
+                }
+                Ok(())
+            }
+            Self::Rebalance { helpers, validators, approvals } => {
+                let before = load_shards(Source::GroupInput)?;
+                let after = load_shards(Source::GroupOutput)?;
+                if before.len() < 2 {
+                    return Err(Error::NothingToRebalance);
+                }
+                // the shards keep their place, lock and data, only capacity moves between them
+                if after.len() != before.len() {
+                    return Err(Error::WrongShard);
+                }
+                for (index, (shard_before, shard_after)) in before.iter().zip(after.iter()).enumerate() {
+                    if shard_after.lock_hash != shard_before.lock_hash {
+                        return Err(Error::WrongLockScript);
+                    }
+                    if shard_after.data.get(0) != shard_before.data.get(0) {
+                        return Err(Error::WrongShard);
+                    }
+                    if shard_after.data != shard_before.data {
+                        return Err(Error::DataUpdatedIncorrectly);
+                    }
+                    verify_bridge_capacity(index, Source::GroupOutput, shard_after.capacity)?;
+                }
+                let cap_before: u64 = before.iter().map(|shard| shard.capacity).sum();
+                let cap_after: u64 = after.iter().map(|shard| shard.capacity).sum();
+                if cap_after != cap_before {
+                    return Err(Error::RebalancedCapacityComputedIncorrectly);
+                }
+                // validators sign the spent shards and where their capacity goes, so the
+                // approval can't be replayed
+                let mut message = load_script_hash()?.to_vec();
+                for input in QueryIter::new(load_input, Source::GroupInput) {
+                    message.extend_from_slice(input.previous_output().as_slice());
+                }
+                for shard in after.iter() {
+                    message.push(shard.data[0]);
+                    message.extend_from_slice(&shard.capacity.to_be_bytes());
+                }
+                let digest = signing_digest(&message, validators.scheme);
+                verify_quorum(&message, &digest, approvals, validators)?;
+                // no tokens move, whichever shard registered them
+                let bridge_data = BridgeData::parse(&before[0].data[1..])?;
+                verify_asset_cells(helpers, &bridge_data, &bridge_data, 0)
+            }
+        }
+    }
+}
+
+/// Validators sign the digest according to their identity scheme. The approvals in the
+/// witness are plain signatures for a validator list, for a merkle root every signature is
+/// preceded by the signer's address (and weight) and followed by the proof length and the
+/// proof. An aggregate key takes a single Schnorr signature over the keccak hash of the message.
+fn verify_quorum(message: &[u8], digest: &Hash, approvals: &[u8], validators: &Validators) -> Result<(), Error> {
+    let scheme = validators.scheme;
+    match &validators.set {
+        ValidatorSet::List(validators) => {
+            if approvals.len() % SIGNATURE_LEN != 0 {
+                return Err(Error::InvalidWitnessEncoding);
+            }
+            let mut quorum = Vec::new();
+            // init verification vector
+            for _ in 0..(validators.len()) {
+                quorum.push(false);
+            }
+            // recover all signers
+            for sig in approvals.chunks(SIGNATURE_LEN) {
+                let addr = recover_address(digest, sig, scheme)?;
+                let pos = get_position(addr, validators)?;
+                quorum[pos] = true;
+            }
+            // determine quorum
+            let mut sig_count = 0;
+            for i in 0..(validators.len()) {
+                if quorum[i] {
+                    sig_count += 1;
+                }
+            }
+            if sig_count < validators.len() * 2 / 3 {
+                return Err(Error::SignatureQuorumNotMet);
+            }
+        }
+        ValidatorSet::WeightedList { validators, threshold } => {
+            if approvals.len() % SIGNATURE_LEN != 0 {
+                return Err(Error::InvalidWitnessEncoding);
+            }
+            let addresses: Vec<Address> = validators.iter().map(|(addr, _)| *addr).collect();
+            let mut quorum = vec![false; validators.len()];
+            for sig in approvals.chunks(SIGNATURE_LEN) {
+                let addr = recover_address(digest, sig, scheme)?;
+                let pos = get_position(addr, &addresses)?;
+                quorum[pos] = true;
+            }
+            // sum the stake of distinct signers
+            let signed_weight = validators
+                .iter()
+                .zip(quorum.iter())
+                .filter(|(_, signed)| **signed)
+                .fold(0u64, |sum, ((_, weight), _)| sum.saturating_add(*weight));
+            if signed_weight < *threshold {
+                return Err(Error::SignatureQuorumNotMet);
+            }
+        }
+        ValidatorSet::MerkleRoot { root, threshold, weighted } => {
+            let mut signers: Vec<Address> = Vec::new();
+            let mut signed_weight: u64 = 0;
+            let mut rest = approvals;
+            // leaves are keccak(address) or keccak(address || weight)
+            let leaf_len = if *weighted { ADDRESS_LEN + WEIGHT_LEN } else { ADDRESS_LEN };
+            while rest.len() > 0 {
+                let proof_offset = leaf_len + SIGNATURE_LEN + 1;
+                if rest.len() < proof_offset {
+                    return Err(Error::InvalidWitnessEncoding);
+                }
+                let addr = slice_to_array_20(&rest[..ADDRESS_LEN]);
+                let weight = if *weighted {
+                    let weight_array: [u8; 8] = (&rest[ADDRESS_LEN..leaf_len])
+                        .try_into()
+                        .map_err(|_| Error::InvalidWitnessEncoding)?;
+                    u64::from_be_bytes(weight_array)
+                } else {
+                    1
+                };
+                let sig = &rest[leaf_len..leaf_len + SIGNATURE_LEN];
+                let proof_end = proof_offset + rest[proof_offset - 1] as usize * 32;
+                if rest.len() < proof_end {
+                    return Err(Error::InvalidWitnessEncoding);
+                }
+                let proof = &rest[proof_offset..proof_end];
+                if recover_address(digest, sig, scheme)? != addr {
+                    return Err(Error::UnknownReceiptSigner);
+                }
+                if !verify_merkle_proof(root, &Keccak256::digest(&rest[..leaf_len])[..], proof) {
+                    return Err(Error::UnknownReceiptSigner);
+                }
+                // count every validator once
+                if !signers.contains(&addr) {
+                    signers.push(addr);
+                    signed_weight = signed_weight.saturating_add(weight);
+                }
+                rest = &rest[proof_end..];
+            }
+            if signed_weight < *threshold {
+                return Err(Error::SignatureQuorumNotMet);
+            }
+        }
+        ValidatorSet::AggregateKey(key) => {
+            if approvals.len() != SCHNORR_SIGNATURE_LEN {
+                return Err(Error::InvalidWitnessEncoding);
+            }
+            if !verify_schnorr(key, &Keccak256::digest(message)[..], approvals) {
+                return Err(Error::InvalidAggregateSignature);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// BIP340 verification: with e = tagged_hash(r || P || m), s * G - e * P has to be the
+/// point with x coordinate r and an even y coordinate.
+fn verify_schnorr(key: &Hash, msg: &[u8], sig: &[u8]) -> bool {
+    // the x-only key stands for the point with even y
+    let compressed_key = [&[0x02u8][..], &key[..]].concat();
+    let key_point = match EncodedPoint::from_bytes(&compressed_key)
+        .ok()
+        .and_then(|encoded| AffinePoint::from_encoded_point(&encoded))
+    {
+        Some(point) => point,
+        None => return false,
+    };
+    let (r, s) = sig.split_at(32);
+    // big endian byte order compares like the numbers
+    if *s >= CURVE_ORDER[..] {
+        return false;
+    }
+    let challenge = tagged_hash(b"BIP0340/challenge", &[r, &key[..], msg].concat());
+    let s = Scalar::from_bytes_reduced(FieldBytes::from_slice(s));
+    let e = Scalar::from_bytes_reduced(FieldBytes::from_slice(&challenge));
+
+    let nonce_point = ProjectivePoint::generator() * &s - ProjectivePoint::from(key_point) * &e;
+    let nonce_point: Option<AffinePoint> = nonce_point.to_affine().into();
+    let encoded = match nonce_point {
+        Some(point) => point.to_encoded_point(false),
+        None => return false,
+    };
+    // the point at infinity has a one byte encoding
+    let bytes = encoded.as_bytes();
+    bytes.len() == 65 && bytes[1..33] == *r && bytes[64] & 1 == 0
+}
+
+fn tagged_hash(tag: &[u8], data: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag);
+    slice_to_array_32(&Sha256::digest(&[&tag_hash[..], &tag_hash[..], data].concat()))
+}
+
+/// Ethereum identities sign like `personal_sign`, CKB identities sign the ckb hash of
+/// the prefixed message.
+fn signing_digest(message: &[u8], scheme: IdentityScheme) -> Hash {
+    match scheme {
+        IdentityScheme::Ethereum | IdentityScheme::Eip712 { .. } => {
+            let preamble = alloc::format!("\x19Ethereum Signed Message:\n{}", message.len());
+            slice_to_array_32(&Keccak256::digest(&[preamble.as_bytes(), message].concat()))
+        }
+        IdentityScheme::Ckb => ckb_hash(&[CKB_MESSAGE_PREFIX, message].concat()),
+    }
+}
+
+/// In EIP-712 mode validators sign the typed data hash of the `Withdrawal` the receipt
+/// stands for, other receipts are signed like any message.
+fn receipt_digest(receipt: &Receipt, scheme: IdentityScheme) -> Hash {
+    let (bridge_id, chain_id) = match scheme {
+        IdentityScheme::Eip712 { bridge_id, chain_id } => (bridge_id, chain_id),
+        _ => return signing_digest(&receipt[..], scheme),
+    };
+    let domain_separator = Keccak256::digest(
+        &[
+            &Keccak256::digest(EIP712_DOMAIN_TYPE)[..],
+            &Keccak256::digest(EIP712_DOMAIN_NAME)[..],
+            &Keccak256::digest(EIP712_DOMAIN_VERSION)[..],
+            &abi_encode_u64(chain_id)[..],
+        ]
+        .concat(),
+    );
+    // amount, owner, txHash, nonce, expiry, fee and asset of the receipt
+    let mut amount_array = [0u8; 8];
+    amount_array.copy_from_slice(&receipt[56..64]);
+    let struct_hash = Keccak256::digest(
+        &[
+            &Keccak256::digest(WITHDRAWAL_TYPE)[..],
+            &bridge_id[..],
+            &receipt[96..128],
+            &receipt[64..96],
+            &abi_encode_u64(u64::from_be_bytes(amount_array))[..],
+            &abi_encode_u64(chain_id)[..],
+            &abi_encode_u64(receipt_nonce(receipt))[..],
+            &abi_encode_u64(receipt_expiry(receipt))[..],
+            &abi_encode_u64(receipt_fee(receipt))[..],
+            &receipt_asset(receipt)[..],
+        ]
+        .concat(),
+    );
+    slice_to_array_32(&Keccak256::digest(
+        &[&b"\x19\x01"[..], &domain_separator[..], &struct_hash[..]].concat(),
+    ))
+}
+
+/// uint256 encoding of a number
+fn abi_encode_u64(value: u64) -> Hash {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Signatures are r || s || v with a low s and v either 0 / 1 or 27 / 28.
+fn recover_address(digest: &Hash, sig: &[u8], scheme: IdentityScheme) -> Result<Address, Error> {
+    if sig[32..64] > HALF_CURVE_ORDER[..] {
+        return Err(Error::HighSSignature);
+    }
+    let recovery_id = match sig[64] {
+        v @ 0..=1 => v,
+        v @ 27..=28 => v - ETHEREUM_V_OFFSET,
+        _ => return Err(Error::InvalidRecoveryId),
+    };
+    let normalized = [&sig[..64], &[recovery_id][..]].concat();
+    let sig: recoverable::Signature = recoverable::Signature::try_from(&normalized[..])
+        .map_err(|_| Error::UnknownReceiptSigner)?;
+    let recovered_key = sig
+        .recover_verify_key_from_digest_bytes(FieldBytes::from_slice(digest))
+        .map_err(|_| Error::UnknownReceiptSigner)?;
+    let addr = match scheme {
+        IdentityScheme::Ethereum | IdentityScheme::Eip712 { .. } => slice_to_array_20(
+            &Keccak256::digest(&recovered_key.to_encoded_point(false).as_bytes()[1..65])[12..],
+        ),
+        IdentityScheme::Ckb => {
+            slice_to_array_20(&ckb_hash(recovered_key.to_encoded_point(true).as_bytes())[..ADDRESS_LEN])
         }
+    };
+    Ok(addr)
+}
+
+/// Pairs are hashed in sorted order like OpenZeppelin's MerkleProof, so proofs need no
+/// position bits.
+fn verify_merkle_proof(root: &Hash, leaf: &[u8], proof: &[u8]) -> bool {
+    let mut node = slice_to_array_32(leaf);
+    for sibling in proof.chunks(32) {
+        let pair = if node[..] <= *sibling {
+            [&node[..], sibling].concat()
+        } else {
+            [sibling, &node[..]].concat()
+        };
+        node = slice_to_array_32(&Keccak256::digest(&pair));
     }
+    node == *root
 }
 
-fn parse_data(data: &Vec<u8>) -> Vec<Vec<u8>> {
-    let mut parsed = Vec::new();
-    for i in 0..(data.len() / 32) {
-        parsed.push(data[(i*32)..(i*32)+32].to_vec());
+/// Header deps only prove that time has passed, so besides the most recent header dep the
+/// block that created the bridge input counts: once the bridge moved after the expiry, an
+/// expired receipt can't be paid out anymore and the childchain may re-issue or refund it.
+fn verify_not_expired(expiry: u64) -> Result<(), Error> {
+    if expiry == 0 {
+        return Ok(());
+    }
+    // the input's block has to be among the header deps
+    let input_header = load_header(0, Source::GroupInput).map_err(|_| Error::ExpiryNotProven)?;
+    let input_timestamp: u64 = input_header.raw().timestamp().unpack();
+    let latest_timestamp = QueryIter::new(load_header, Source::HeaderDep)
+        .map(|header| {
+            let timestamp: u64 = header.raw().timestamp().unpack();
+            timestamp
+        })
+        .fold(input_timestamp, u64::max);
+    if latest_timestamp > expiry {
+        return Err(Error::ReceiptExpired);
     }
-    parsed
+    Ok(())
+}
+
+/// The bridge output has to pay for its own lock, type and data, or the payout would
+/// leave it unusable. The bridge data doesn't grow, so that is all it ever needs.
+fn verify_bridge_capacity(index: usize, source: Source, cap_after: u64) -> Result<(), Error> {
+    let occupied = load_cell_occupied_capacity(index, source)?;
+    if cap_after < occupied {
+        return Err(Error::BridgeCapacityTooLow);
+    }
+    Ok(())
+}
+
+/// The asset cells of the bridge are locked by its bridge guard and hold the registered
+/// tokens. Across all of them a token amount only changes as much as its balance in the
+/// registry, and their capacity grows by at least the capacity of the token deposits
+/// collected into them. Tokens outside the registry may belong to another shard and
+/// can't move at all.
+fn verify_asset_cells(
+    helpers: &HelperScripts,
+    before: &BridgeData,
+    after: &BridgeData,
+    deposited_capacity: u64,
+) -> Result<(), Error> {
+    let inputs = load_asset_cells(helpers, Source::Input)?;
+    let outputs = load_asset_cells(helpers, Source::Output)?;
+    let cap_in: u64 = inputs.iter().map(|(_, cap, _)| cap).sum();
+    let cap_out: u64 = outputs.iter().map(|(_, cap, _)| cap).sum();
+    if cap_out < cap_in.saturating_add(deposited_capacity) {
+        return Err(Error::AssetCellsComputedIncorrectly);
+    }
+    let mut assets: Vec<Hash> = after.registry.iter().map(|(asset, _)| *asset).collect();
+    for (type_hash, _, _) in inputs.iter() {
+        if let Some(asset) = type_hash {
+            if !assets.contains(asset) {
+                assets.push(*asset);
+            }
+        }
+    }
+    for asset in assets.iter() {
+        let amount_in = sum_asset_cells(&inputs, asset)?;
+        let amount_out = sum_asset_cells(&outputs, asset)?;
+        // out - in has to be after - before, rearranged to stay unsigned
+        let lhs = amount_out.checked_add(before.balance(asset));
+        let rhs = amount_in.checked_add(after.balance(asset));
+        if lhs.is_none() || lhs != rhs {
+            return Err(Error::AssetCellsComputedIncorrectly);
+        }
+    }
+    Ok(())
+}
+
+/// Type hash, capacity and data of the cells locked by the bridge guard of this bridge,
+/// the bridge cell itself aside. The guard's args have to name this bridge and its
+/// trustee, a guard with another trustee lets that trustee take the cells.
+fn load_asset_cells(helpers: &HelperScripts, source: Source) -> Result<Vec<(Option<Hash>, u64, Vec<u8>)>, Error> {
+    let my_hash = load_script_hash()?;
+    let trustee = parse_trustee_from_args(&load_script()?.args().raw_data())?;
+    let guard_args = [&my_hash[..], &trustee[..]].concat();
+    Ok(QueryIter::new(load_cell_lock, source)
+        .zip(QueryIter::new(load_cell_type_hash, source))
+        .zip(QueryIter::new(load_cell_capacity, source))
+        .zip(QueryIter::new(load_cell_data, source))
+        .filter(|(((lock, type_hash), _), _)| {
+            is_script(lock, &helpers.bridge_guard)
+                && lock.args().raw_data()[..] == guard_args[..]
+                && *type_hash != Some(my_hash)
+        })
+        .map(|(((_, type_hash), cap), data)| (type_hash, cap, data))
+        .collect())
+}
+
+fn sum_asset_cells(cells: &[(Option<Hash>, u64, Vec<u8>)], asset: &Hash) -> Result<u128, Error> {
+    cells
+        .iter()
+        .filter(|(type_hash, _, _)| *type_hash == Some(*asset))
+        .try_fold(0u128, |sum, (_, _, data)| {
+            udt_amount(data)
+                .and_then(|amount| sum.checked_add(amount))
+                .ok_or(Error::AssetCellsComputedIncorrectly)
+        })
+}
+
+fn verify_trustee_signed(trustee: &Hash) -> Result<(), Error> {
+    //Is trustee signer of any input?
+    let trustee_signed = QueryIter::new(load_cell_lock_hash, Source::Input)
+        .filter(|hash| hash == trustee)
+        .count()
+        > 0;
+    if !trustee_signed {
+        return Err(Error::NotSignedByTrustee);
+    }
+    Ok(())
+}
+
+fn verify_bridge_not_in_outputs() -> Result<(), Error> {
+    //Check there is no bridge in the outputs
+    let my_hash = load_script_hash()?;
+    let is_bridge_in_outputs = QueryIter::new(load_cell_type_hash, Source::Output)
+        .filter(|option| option.map_or(false, |hash| hash == my_hash))
+        .count()
+        > 0;
+    if is_bridge_in_outputs {
+        return Err(Error::BridgeWasNotDissolved);
+    }
+    Ok(())
+}
+
+fn parse_u64(bytes: &[u8]) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(bytes);
+    u64::from_be_bytes(array)
+}
+
+fn receipt_nonce(receipt: &[u8]) -> u64 {
+    parse_u64(&receipt[NONCE_OFFSET..NONCE_OFFSET + 8])
+}
+
+fn receipt_expiry(receipt: &[u8]) -> u64 {
+    parse_u64(&receipt[EXPIRY_OFFSET..EXPIRY_OFFSET + 8])
+}
+
+fn receipt_fee(receipt: &[u8]) -> u64 {
+    parse_u64(&receipt[FEE_OFFSET..FEE_OFFSET + 8])
+}
+
+/// A sharded bridge pays a receipt out of the shard its nonce falls on, every shard marks
+/// the nonces of its own receipts.
+fn shard_nonce(receipt: &[u8], shard: &Shard) -> Result<u64, Error> {
+    let nonce = receipt_nonce(receipt);
+    if nonce % shard.count as u64 != shard.index as u64 {
+        return Err(Error::WrongShard);
+    }
+    Ok(nonce / shard.count as u64)
+}
+
+fn receipt_asset(receipt: &[u8]) -> Hash {
+    slice_to_array_32(&receipt[ASSET_OFFSET..ASSET_OFFSET + 32])
+}
+
+/// sUDT amount at the start of the cell data
+fn udt_amount(data: &[u8]) -> Option<u128> {
+    let amount: [u8; UDT_AMOUNT_LEN] = data.get(..UDT_AMOUNT_LEN)?.try_into().ok()?;
+    Some(u128::from_le_bytes(amount))
 }
 
 fn slice_to_array_20(slice: &[u8]) -> [u8; 20] {
@@ -422,10 +1541,109 @@ fn get_position(address: Address, vec: &Vec<Address>) -> Result<usize, Error> {
     return Err(Error::UnknownReceiptSigner); // todo: use proper error
 }
 
-fn parse_validator_list_from_args(args: &[u8]) -> Result<Vec<Address>, Error> {
-    // args consist of outpount + validator list
-    // output has length of 36 bytes + trustee is 32 bytes
-    let val_args = &args[68..];
+fn parse_validator_set_from_args(args: &[u8]) -> Result<Validators, Error> {
+    // args consist of type id + trustee + min deposit + config + validator mode + validator set
+    let mode = args[VALIDATORS_OFFSET];
+    let mut val_args = &args[VALIDATORS_OFFSET + 1..];
+    let scheme = match (mode & IDENTITY_CKB_FLAG != 0, mode & EIP712_FLAG != 0) {
+        (false, false) => IdentityScheme::Ethereum,
+        (true, false) => IdentityScheme::Ckb,
+        (false, true) => {
+            let chain_id_array: [u8; 8] = val_args
+                .get(..CHAIN_ID_LEN)
+                .and_then(|chain_id| chain_id.try_into().ok())
+                .ok_or(Error::InvalidArgsEncoding)?;
+            val_args = &val_args[CHAIN_ID_LEN..];
+            IdentityScheme::Eip712 {
+                bridge_id: slice_to_array_32(&args[0..32]),
+                chain_id: u64::from_be_bytes(chain_id_array),
+            }
+        }
+        // typed data is an Ethereum signing scheme
+        (true, true) => return Err(Error::InvalidArgsEncoding),
+    };
+    // the shard count is read by parse_shard_count_from_args
+    if mode & SHARDED_FLAG != 0 {
+        val_args = val_args.get(1..).ok_or(Error::InvalidArgsEncoding)?;
+    }
+    let mode = mode & !(IDENTITY_CKB_FLAG | EIP712_FLAG | SHARDED_FLAG);
+    // the aggregate key has no identities
+    if scheme != IdentityScheme::Ethereum && mode == VALIDATOR_MODE_SCHNORR {
+        return Err(Error::InvalidArgsEncoding);
+    }
+    Ok(Validators {
+        scheme: scheme,
+        set: parse_validator_set(mode, val_args)?,
+    })
+}
+
+fn parse_validator_set(mode: u8, val_args: &[u8]) -> Result<ValidatorSet, Error> {
+    match mode {
+        VALIDATOR_MODE_LIST => {
+            let validators = parse_validator_list(val_args)?;
+            if validators.len() == 0 {
+                return Err(Error::EmptyValidatorList);
+            };
+            debug!("validators: {:?}", validators);
+            Ok(ValidatorSet::List(validators))
+        }
+        VALIDATOR_MODE_WEIGHTED_LIST => {
+            // threshold followed by address and weight of every validator
+            if val_args.len() < WEIGHT_LEN {
+                return Err(Error::InvalidArgsEncoding);
+            }
+            let threshold = parse_threshold(&val_args[..WEIGHT_LEN])?;
+            let entries = &val_args[WEIGHT_LEN..];
+            if entries.len() % (ADDRESS_LEN + WEIGHT_LEN) != 0 {
+                return Err(Error::InvalidArgsEncoding);
+            }
+            let validators: Vec<(Address, u64)> = entries
+                .chunks(ADDRESS_LEN + WEIGHT_LEN)
+                .map(|entry| {
+                    let mut weight_array = [0u8; 8];
+                    weight_array.copy_from_slice(&entry[ADDRESS_LEN..]);
+                    (slice_to_array_20(&entry[..ADDRESS_LEN]), u64::from_be_bytes(weight_array))
+                })
+                .collect();
+            if validators.len() == 0 {
+                return Err(Error::EmptyValidatorList);
+            }
+            Ok(ValidatorSet::WeightedList {
+                validators: validators,
+                threshold: threshold,
+            })
+        }
+        VALIDATOR_MODE_SCHNORR => {
+            if val_args.len() != SCHNORR_KEY_LEN {
+                return Err(Error::InvalidArgsEncoding);
+            }
+            Ok(ValidatorSet::AggregateKey(slice_to_array_32(val_args)))
+        }
+        mode @ VALIDATOR_MODE_MERKLE | mode @ VALIDATOR_MODE_WEIGHTED_MERKLE => {
+            if val_args.len() != MERKLE_ARGS_LEN {
+                return Err(Error::InvalidArgsEncoding);
+            }
+            Ok(ValidatorSet::MerkleRoot {
+                root: slice_to_array_32(&val_args[..32]),
+                threshold: parse_threshold(&val_args[32..40])?,
+                weighted: mode == VALIDATOR_MODE_WEIGHTED_MERKLE,
+            })
+        }
+        _ => Err(Error::InvalidArgsEncoding),
+    }
+}
+
+fn parse_threshold(threshold_args: &[u8]) -> Result<u64, Error> {
+    let threshold_array: [u8; 8] = threshold_args.try_into().map_err(|_| Error::InvalidArgsEncoding)?;
+    let threshold = u64::from_be_bytes(threshold_array);
+    // a zero threshold would let anyone pay out
+    if threshold == 0 {
+        return Err(Error::EmptyValidatorList);
+    }
+    Ok(threshold)
+}
+
+fn parse_validator_list(val_args: &[u8]) -> Result<Vec<Address>, Error> {
     // validator address
     if val_args.len() % ADDRESS_LEN != 0 {
         return Err(Error::InvalidArgsEncoding);
@@ -438,15 +1656,90 @@ fn parse_validator_list_from_args(args: &[u8]) -> Result<Vec<Address>, Error> {
     Ok(validators)
 }
 
+fn parse_shard_count_from_args(args: &[u8]) -> Result<u8, Error> {
+    let mode = args[VALIDATORS_OFFSET];
+    if mode & SHARDED_FLAG == 0 {
+        return Ok(1);
+    }
+    let chain_id_len = if mode & EIP712_FLAG != 0 { CHAIN_ID_LEN } else { 0 };
+    match args.get(VALIDATORS_OFFSET + 1 + chain_id_len) {
+        Some(&count) if count > 0 => Ok(count),
+        _ => Err(Error::InvalidArgsEncoding),
+    }
+}
+
 fn parse_trustee_from_args(args: &[u8]) -> Result<[u8;32], Error> {
-    Ok(slice_to_array_32(&args[36..68]))
+    Ok(slice_to_array_32(&args[32..64]))
+}
+
+fn parse_min_deposit_from_args(args: &[u8]) -> Result<u64, Error> {
+    let min_deposit_array: [u8; 8] = (&args[64..72]).try_into().map_err(|_| Error::InvalidArgsEncoding)?;
+    Ok(u64::from_be_bytes(min_deposit_array))
+}
+
+fn parse_config_from_args(args: &[u8]) -> Result<Hash, Error> {
+    Ok(slice_to_array_32(&args[72..104]))
+}
+
+/// Without a config cell (all zero type hash) the helper scripts compiled into the
+/// bridge are used, otherwise the config cell has to be among the cell deps. Anyone can
+/// create a cell with an ordinary type script, so only a Type ID config is trusted.
+fn load_helper_scripts(config: &Hash) -> Result<HelperScripts, Error> {
+    if *config == [0u8; 32] {
+        return Ok(HelperScripts {
+            deposit_lock: ScriptId { code_hash: CODE_HASH_DEPOSIT_LOCK, hash_type: HASH_TYPE_DEPOSIT_LOCK },
+            audit_delay: ScriptId { code_hash: CODE_HASH_AUDIT_DELAY, hash_type: HASH_TYPE_AUDIT_DELAY },
+            bridge_guard: ScriptId { code_hash: CODE_HASH_BRIDGE_GUARD, hash_type: HASH_TYPE_BRIDGE_GUARD },
+        });
+    }
+    let index = QueryIter::new(load_cell_type_hash, Source::CellDep)
+        .position(|option| option.map_or(false, |hash| hash == *config))
+        .ok_or(Error::ConfigCellNotFound)?;
+    let config_type = load_cell_type(index, Source::CellDep)?.ok_or(Error::ConfigCellNotFound)?;
+    if config_type.code_hash().as_slice() != TYPE_ID_CODE_HASH
+        || config_type.hash_type().as_slice()[0] != HASH_TYPE_TYPE
+    {
+        return Err(Error::ConfigNotTypeId);
+    }
+    let data = load_cell_data(index, Source::CellDep)?;
+    if data.len() != CONFIG_DATA_LEN {
+        return Err(Error::InvalidConfigData);
+    }
+    let script_id = |i: usize| {
+        let offset = i * SCRIPT_ID_LEN;
+        ScriptId {
+            code_hash: slice_to_array_32(&data[offset..offset + 32]),
+            hash_type: data[offset + 32],
+        }
+    };
+    Ok(HelperScripts {
+        deposit_lock: script_id(0),
+        audit_delay: script_id(1),
+        bridge_guard: script_id(2),
+    })
+}
+
+/// The id of a new bridge follows the Type ID rule: the hash of the first input
+/// of the deploy transaction and the index of the bridge output.
+fn calc_type_id() -> Result<Bytes, Error> {
+    let my_hash = load_script_hash()?;
+    let output_index = QueryIter::new(load_cell_type_hash, Source::Output)
+        .position(|option| option.map_or(false, |hash| hash == my_hash))
+        .ok_or(Error::WrongTypeScript)? as u64;
+    let first_input = load_input(0, Source::Input)?;
+    let type_id = ckb_hash(&[first_input.as_slice(), &output_index.to_le_bytes()[..]].concat());
+    Ok(Bytes::from(type_id.to_vec()))
 }
 
-fn get_state_id() -> Result<Bytes, Error> {
-    let outpoint = load_input_out_point(0, Source::Input)?;
-    let tx_hash: &[u8] = &*outpoint.tx_hash().raw_data();
-    let index: &[u8] = &*outpoint.index().raw_data();
-    Ok(Bytes::from([tx_hash, index].concat()))
+/// blake2b with the personalization CKB uses for script hashes and blake160
+fn ckb_hash(data: &[u8]) -> Hash {
+    let mut blake2b = Blake2bBuilder::new(32)
+        .personal(CKB_HASH_PERSONALIZATION)
+        .build();
+    blake2b.update(data);
+    let mut hash = [0u8; 32];
+    blake2b.finalize(&mut hash);
+    hash
 }
 
 fn verify_state_id() -> Result<(), Error> {
@@ -459,20 +1752,85 @@ fn verify_state_id() -> Result<(), Error> {
     Ok(())
 }
 
-// check there is always only one
-fn only_one_output_has_state_id() -> Result<(), Error> {
+// the bridge guard on the bridge cell has to survive every transition
+fn verify_lock_unchanged() -> Result<(), Error> {
+    if load_cell_lock_hash(0, Source::GroupInput)? != load_cell_lock_hash(0, Source::GroupOutput)? {
+        return Err(Error::WrongLockScript);
+    }
+    Ok(())
+}
+
+/// A script only matches a helper when both code hash and hash type do, data and data1
+/// run the same binary on different VM versions.
+fn is_script(script: &Script, id: &ScriptId) -> bool {
+    *script.code_hash().raw_data() == id.code_hash[..] && script.hash_type().as_slice()[0] == id.hash_type
+}
+
+fn at_most_one_input_per_shard(shards: u8) -> Result<(), Error> {
+    if QueryIter::new(load_input, Source::GroupInput).count() > shards as usize {
+        return Err(Error::TooManyTypeInputs);
+    }
+    Ok(())
+}
+
+// check there is never more than one cell per shard
+fn at_most_one_output_per_shard(shards: u8) -> Result<(), Error> {
     //load currently executed script, in this case Bridge type script
     let my_hash = load_script_hash()?;
     //check how many times identical script appears in Outputs
     let num = QueryIter::new(load_cell_type_hash, Source::Output)
         .filter(|option| option.map_or(false, |hash| hash == my_hash))
         .count();
-    if num > 1 {
+    if num > shards as usize {
         return Err(Error::TooManyTypeOutputs);
     };
     Ok(())
 }
 
+// payouts, deposits, vetoes and migrations spend a single shard
+fn only_one_shard() -> Result<(), Error> {
+    at_most_one_input_per_shard(1)?;
+    at_most_one_output_per_shard(1)
+}
+
+/// Splits the shard index off the bridge data of a sharded bridge, the shard has to keep it.
+fn split_shard(mut data_before: Vec<u8>, mut data_after: Vec<u8>, count: u8) -> Result<(Shard, Vec<u8>, Vec<u8>), Error> {
+    if count == 1 {
+        return Ok((Shard { index: 0, count: 1 }, data_before, data_after));
+    }
+    let index = *data_before.get(0).ok_or(Error::WrongShard)?;
+    if index >= count || data_after.get(0) != Some(&index) {
+        return Err(Error::WrongShard);
+    }
+    data_before.remove(0);
+    data_after.remove(0);
+    Ok((Shard { index: index, count: count }, data_before, data_after))
+}
+
+/// A shard cell of this bridge in a rebalance
+struct ShardCell {
+    lock_hash: Hash,
+    capacity: u64,
+    // shard index followed by the bridge data
+    data: Vec<u8>,
+}
+
+fn load_shards(source: Source) -> Result<Vec<ShardCell>, Error> {
+    let shards: Vec<ShardCell> = QueryIter::new(load_cell_lock_hash, source)
+        .zip(QueryIter::new(load_cell_capacity, source))
+        .zip(QueryIter::new(load_cell_data, source))
+        .map(|((lock_hash, capacity), data)| ShardCell {
+            lock_hash: lock_hash,
+            capacity: capacity,
+            data: data,
+        })
+        .collect();
+    if shards.iter().any(|shard| shard.data.len() == 0) {
+        return Err(Error::WrongShard);
+    }
+    Ok(shards)
+}
+
 fn main() -> Result<(), Error> {
     let state_transition = StateTransition::get()?;
     state_transition.verify()
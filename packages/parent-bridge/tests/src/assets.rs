@@ -0,0 +1,385 @@
+use super::*;
+use crate::payout::get_val_keys;
+use ckb_testtool::context::Context;
+use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::ckb_script::{TransactionScriptError, ScriptError};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::Rng;
+use sha3::{Digest, Keccak256};
+
+// capacity of the bridge cell on top of what it needs itself
+const BRIDGE_CAPACITY: u64 = 1000;
+const ASSET_CELL_CAPACITY: u64 = 100;
+
+enum Action {
+    // pay a token receipt out of the asset cells
+    Payout,
+    // collect a token deposit into the asset cells
+    CollectDeposits,
+}
+
+struct AssetTestParams {
+    action: Action,
+    // token balance in the registry, none if the token isn't registered
+    balance_before: Option<u128>,
+    balance_after: Option<u128>,
+    // token amounts of the asset cell the transaction spends and recreates
+    asset_cell_before: u128,
+    asset_cell_after: u128,
+    // the recreated asset cell is guarded for another trustee
+    asset_cell_after_other_trustee: bool,
+    // receipt or deposit amount
+    amount: u64,
+    deposit_capacity: u64,
+    // the asset cells don't grow by the capacity of the token deposit
+    sweeper_keeps_deposit_capacity: bool,
+    // defaults to the receipt amount
+    payout_amount: Option<u128>,
+    // the payout cell holds another token than the receipt names
+    payout_other_token: bool,
+    trustee_signs: bool,
+    error: Option<TransactionScriptError>,
+}
+
+impl Default for AssetTestParams {
+    fn default() -> Self {
+        AssetTestParams {
+            action: Action::Payout,
+            balance_before: Some(100),
+            balance_after: Some(90),
+            asset_cell_before: 100,
+            asset_cell_after: 90,
+            asset_cell_after_other_trustee: false,
+            amount: 10,
+            deposit_capacity: ASSET_CELL_CAPACITY,
+            sweeper_keeps_deposit_capacity: false,
+            payout_amount: None,
+            payout_other_token: false,
+            trustee_signs: false,
+            error: None,
+        }
+    }
+}
+
+fn udt_data(amount: u128) -> Bytes {
+    Bytes::from(amount.to_le_bytes().to_vec())
+}
+
+fn test_assets(params: AssetTestParams) {
+    let mut context = Context::default();
+    let fixture = BridgeFixture::deploy(&mut context);
+
+    // make our script objects
+    let (priv_key, pub_key) = get_val_keys();
+    let validator_address = &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
+    let always_success_script = fixture.always_success_script.clone();
+    let trustee_script = fixture.stand_in_script(&mut context, 1);
+    let stranger_script = fixture.stand_in_script(&mut context, 2);
+    let token_script = fixture.stand_in_script(&mut context, 3);
+    let other_token_script = fixture.stand_in_script(&mut context, 4);
+    let trustee_hash = trustee_script.calc_script_hash().raw_data();
+    let mut token_hash = [0u8; 32];
+    token_hash.copy_from_slice(&token_script.calc_script_hash().raw_data());
+    let bridge_script = fixture.bridge_script(
+        &mut context,
+        &[0u8; 32],
+        &trustee_hash,
+        &[&[VALIDATOR_MODE_LIST][..], validator_address].concat(),
+    );
+    let bridge_hash = bridge_script.calc_script_hash().raw_data();
+    // asset cells are guarded like the bridge cell
+    let asset_lock_script = fixture.bridge_guard_script(&mut context, &bridge_hash, &trustee_hash);
+
+    // bridge data before and after, the counters only change on payouts
+    let registry = |balance: Option<u128>| match balance {
+        Some(balance) => vec![(token_hash, balance)],
+        None => Vec::new(),
+    };
+    let (data_before, data_after) = match params.action {
+        Action::Payout => (
+            with_registry(bridge_data(0, 0, 0, checkpoint(0, &[])), &registry(params.balance_before)),
+            with_registry(bridge_data(0, 0, 1, checkpoint(1, &[0])), &registry(params.balance_after)),
+        ),
+        Action::CollectDeposits => (
+            with_registry(bridge_data(0, 0, 0, checkpoint(0, &[])), &registry(params.balance_before)),
+            with_registry(bridge_data(0, 0, 0, checkpoint(0, &[])), &registry(params.balance_after)),
+        ),
+    };
+    let bridge_output = CellOutput::new_builder()
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build();
+    let bridge_capacity = BRIDGE_CAPACITY + bridge_capacity_floor(&bridge_output, data_after.len());
+
+    // input outpoints
+    let prev_bridge_outpoint = context.create_cell(
+        bridge_output.clone().as_builder().capacity(bridge_capacity.pack()).build(),
+        data_before,
+    );
+    let asset_cell = CellOutput::new_builder()
+        .capacity(ASSET_CELL_CAPACITY.pack())
+        .lock(asset_lock_script.clone())
+        .type_(Some(token_script.clone()).pack())
+        .build();
+    let asset_cell_outpoint = context.create_cell(asset_cell.clone(), udt_data(params.asset_cell_before));
+    let consent_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(if params.trustee_signs {
+                trustee_script.clone()
+            } else {
+                stranger_script.clone()
+            })
+            .build(),
+        Bytes::default(),
+    );
+
+    let mut inputs = vec![
+        // bridge input
+        CellInput::new_builder()
+            .previous_output(prev_bridge_outpoint)
+            .build(),
+        // asset cell input
+        CellInput::new_builder()
+            .previous_output(asset_cell_outpoint)
+            .build(),
+        // trustee or stranger input
+        CellInput::new_builder()
+            .previous_output(consent_outpoint)
+            .build(),
+    ];
+    let mut outputs = vec![
+        // bridge output
+        bridge_output.as_builder().capacity(bridge_capacity.pack()).build(),
+    ];
+    let mut outputs_data = vec![data_after];
+    let mut asset_cell_capacity_after = ASSET_CELL_CAPACITY;
+
+    let bridge_witness = match params.action {
+        Action::Payout => {
+            let owner_hash = rand::thread_rng().gen::<[u8; 32]>();
+            let mut receipt = [0u8; 128];
+            receipt[0..32].copy_from_slice(&token_hash);
+            receipt[56..64].copy_from_slice(&params.amount.to_be_bytes());
+            receipt[64..96].copy_from_slice(&owner_hash);
+            receipt[96..128].copy_from_slice(&rand::thread_rng().gen::<[u8; 32]>());
+            let sig = personal_sign(&receipt, &priv_key);
+
+            // the payout keeps its sUDT amount in front of the receipt hash
+            let audit_delay_script = context
+                .build_script(
+                    &fixture.audit_delay_out_point,
+                    Bytes::from([&*trustee_hash, &owner_hash[..], &TIMEOUT.to_be_bytes()[..], &*bridge_hash].concat()),
+                )
+                .expect("script");
+            let payout_token = if params.payout_other_token {
+                other_token_script.clone()
+            } else {
+                token_script.clone()
+            };
+            outputs.push(
+                CellOutput::new_builder()
+                    .capacity(ASSET_CELL_CAPACITY.pack())
+                    .lock(audit_delay_script)
+                    .type_(Some(payout_token).pack())
+                    .build(),
+            );
+            let payout_amount = params.payout_amount.unwrap_or(params.amount as u128);
+            outputs_data.push(Bytes::from(
+                [&payout_amount.to_le_bytes()[..], &Keccak256::digest(&receipt[..])[..]].concat(),
+            ));
+            Bytes::from([&[0u8][..], &receipt[..], &sig[..]].concat())
+        }
+        Action::CollectDeposits => {
+            let deposit_lock_script = context
+                .build_script(&fixture.deposit_lock_out_point, Bytes::from([&[0u8; 32][..], &*bridge_hash].concat()))
+                .expect("script");
+            let deposit_outpoint = context.create_cell(
+                CellOutput::new_builder()
+                    .capacity(params.deposit_capacity.pack())
+                    .lock(deposit_lock_script)
+                    .type_(Some(token_script.clone()).pack())
+                    .build(),
+                udt_data(params.amount as u128),
+            );
+            inputs.push(CellInput::new_builder().previous_output(deposit_outpoint).build());
+            if !params.sweeper_keeps_deposit_capacity {
+                asset_cell_capacity_after += params.deposit_capacity;
+            }
+            Bytes::from(vec![1u8])
+        }
+    };
+
+    // asset cell output
+    let asset_cell = asset_cell
+        .as_builder()
+        .capacity(asset_cell_capacity_after.pack())
+        .build();
+    let asset_cell = if params.asset_cell_after_other_trustee {
+        let stranger_hash = stranger_script.calc_script_hash().raw_data();
+        let other_lock_script = fixture.bridge_guard_script(&mut context, &bridge_hash, &stranger_hash);
+        asset_cell.as_builder().lock(other_lock_script).build()
+    } else {
+        asset_cell
+    };
+    outputs.push(asset_cell);
+    outputs_data.push(udt_data(params.asset_cell_after));
+
+    let witnesses = vec![bridge_witness, Bytes::new(), Bytes::new(), Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_deps(fixture.cell_deps)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    check_tx(&context, &tx, params.error);
+}
+
+#[test]
+fn test_token_payout() {
+    test_assets(Default::default());
+}
+
+#[test]
+fn test_token_payout_unknown_asset() {
+    test_assets(AssetTestParams {
+        balance_before: None,
+        balance_after: None,
+        error: Some(ScriptError::ValidationFailure(53).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_token_payout_balance_too_low() {
+    test_assets(AssetTestParams {
+        balance_before: Some(5),
+        balance_after: Some(0),
+        error: Some(ScriptError::ValidationFailure(54).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_token_payout_wrong_token() {
+    test_assets(AssetTestParams {
+        payout_other_token: true,
+        error: Some(ScriptError::ValidationFailure(55).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_token_payout_wrong_amount() {
+    test_assets(AssetTestParams {
+        payout_amount: Some(9),
+        error: Some(ScriptError::ValidationFailure(15).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_token_payout_drains_asset_cells() {
+    test_assets(AssetTestParams {
+        asset_cell_after: 80,
+        error: Some(ScriptError::ValidationFailure(56).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_token_payout_moves_asset_cell_to_other_trustee() {
+    test_assets(AssetTestParams {
+        asset_cell_after_other_trustee: true,
+        error: Some(ScriptError::ValidationFailure(56).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_token_deposit() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_after: Some(110),
+        asset_cell_after: 110,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_token_deposit_not_in_asset_cells() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_after: Some(110),
+        asset_cell_after: 100,
+        error: Some(ScriptError::ValidationFailure(56).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_token_deposit_below_minimum_capacity() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_after: Some(110),
+        asset_cell_after: 110,
+        deposit_capacity: MIN_DEPOSIT - 1,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_token_deposit_without_tokens() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_after: Some(100),
+        asset_cell_after: 100,
+        amount: 0,
+        error: Some(ScriptError::ValidationFailure(30).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_token_deposit_keeping_its_capacity() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_after: Some(110),
+        asset_cell_after: 110,
+        sweeper_keeps_deposit_capacity: true,
+        error: Some(ScriptError::ValidationFailure(56).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_registers_token() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_before: None,
+        balance_after: Some(10),
+        asset_cell_before: 0,
+        asset_cell_after: 10,
+        trustee_signs: true,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_collect_registers_token_without_trustee() {
+    test_assets(AssetTestParams {
+        action: Action::CollectDeposits,
+        balance_before: None,
+        balance_after: Some(10),
+        asset_cell_before: 0,
+        asset_cell_after: 10,
+        error: Some(ScriptError::ValidationFailure(18).input_type_script(0)),
+        ..Default::default()
+    });
+}
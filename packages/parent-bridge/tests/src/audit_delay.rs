@@ -0,0 +1,875 @@
+use super::*;
+use ckb_testtool::context::Context;
+use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::{ckb_error::assert_error_eq, ckb_script::{TransactionScriptError, ScriptError}};
+use rand::Rng;
+
+const PAYOUT_CAPACITY: u64 = 1_000_000;
+const MAX_FINALIZE_FEE: u64 = 100_000;
+
+const SINCE_RELATIVE_FLAG: u64 = 0x8000_0000_0000_0000;
+const SINCE_METRIC_EPOCH: u64 = 0x2000_0000_0000_0000;
+const SINCE_METRIC_TIMESTAMP: u64 = 0x4000_0000_0000_0000;
+
+fn relative_epoch(number: u64, index: u64, length: u64) -> u64 {
+    SINCE_RELATIVE_FLAG | SINCE_METRIC_EPOCH | (length << 40) | (index << 24) | number
+}
+
+enum Spender {
+    Owner,
+    // owner cell comes after the audit delay cell
+    OwnerLast,
+    // owner hash names the type script of the spending cell
+    OwnerType,
+    Trustee,
+    Stranger,
+}
+
+struct AuditDelayTestParams {
+    timeout: u64,
+    since: u64,
+    // timestamps of the header the audit delay cell was created in and of the proof header
+    header_timestamps: Option<(u64, u64)>,
+    spender: Spender,
+    // name a bridge in the args, which restricts the trustee to returning the payout
+    restricted: bool,
+    // capacity added to a bridge cell in the transaction, if any
+    returned_to_bridge: Option<u64>,
+    // status byte after the receipt hash in the cell data, if any
+    status: Option<u8>,
+    // status of a payout recreated with the same lock, if any
+    recreated_status: Option<u8>,
+    // capacity sent to the owner lock by a third party, if any
+    finalized_capacity: Option<u64>,
+    error: Option<TransactionScriptError>,
+}
+
+fn test_audit_delay(params: AuditDelayTestParams) {
+    let mut context = Context::default();
+
+    // load binaries
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let audit_delay_bin: Bytes = Loader::default().load_binary("audit-delay");
+
+    // deploy binaries to cells
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let audit_delay_out_point = context.deploy_cell(audit_delay_bin);
+
+    // make dep objects for our contracts
+    let always_success_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let audit_delay_dep = CellDep::new_builder()
+        .out_point(audit_delay_out_point.clone())
+        .build();
+
+    let always_success_script = context
+        .build_script(&always_success_out_point, Bytes::default())
+        .expect("script");
+    // owner, trustee and a stranger only differ by their args
+    let owner_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+        .expect("script");
+    let stranger_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![3u8]))
+        .expect("script");
+    // stands in for the bridge type script
+    let bridge_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![4u8]))
+        .expect("script");
+
+    // audit delay args
+    let bridge_hash_arg = if params.restricted {
+        bridge_script.calc_script_hash().raw_data()
+    } else {
+        Bytes::new()
+    };
+    let audit_delay_args = Bytes::from(
+        [
+            &*trustee_script.calc_script_hash().raw_data(),
+            &*owner_script.calc_script_hash().raw_data(),
+            &params.timeout.to_be_bytes()[..],
+            &*bridge_hash_arg,
+        ]
+        .concat(),
+    );
+    let audit_delay_script = context
+        .build_script(&audit_delay_out_point, audit_delay_args)
+        .expect("script");
+
+    // input outpoints
+    let owner_last = match params.spender {
+        Spender::OwnerLast => true,
+        _ => false,
+    };
+    let (spender_script, spender_type) = match params.spender {
+        Spender::Owner | Spender::OwnerLast => (owner_script.clone(), None),
+        Spender::OwnerType => (stranger_script, Some(owner_script.clone())),
+        Spender::Trustee => (trustee_script, None),
+        Spender::Stranger => (stranger_script, None),
+    };
+    let spender_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(spender_script.clone())
+            .type_(spender_type.pack())
+            .build(),
+        Bytes::default(),
+    );
+    let receipt_hash = rand::thread_rng().gen::<[u8; 32]>();
+    let status: Vec<u8> = params.status.into_iter().collect();
+    let audit_delay_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(PAYOUT_CAPACITY.pack())
+            .lock(audit_delay_script.clone())
+            .build(),
+        Bytes::from([&receipt_hash[..], &status[..]].concat()),
+    );
+
+    let mut inputs = vec![
+        // spender input
+        CellInput::new_builder()
+            .previous_output(spender_outpoint)
+            .build(),
+        // audit delay input
+        CellInput::new_builder()
+            .previous_output(audit_delay_outpoint.clone())
+            .since(params.since.pack())
+            .build(),
+    ];
+    if owner_last {
+        inputs.swap(0, 1);
+    }
+
+    let mut outputs = vec![CellOutput::new_builder()
+        .capacity((10 + PAYOUT_CAPACITY).pack())
+        .lock(spender_script.clone())
+        .build()];
+
+    let mut outputs_data = vec![Bytes::new()];
+
+    if let Some(finalized_capacity) = params.finalized_capacity {
+        outputs[0] = CellOutput::new_builder()
+            .capacity((10 + PAYOUT_CAPACITY - finalized_capacity).pack())
+            .lock(spender_script.clone())
+            .build();
+        outputs.push(
+            CellOutput::new_builder()
+                .capacity(finalized_capacity.pack())
+                .lock(owner_script.clone())
+                .build(),
+        );
+        outputs_data.push(Bytes::new());
+    }
+
+    if let Some(recreated_status) = params.recreated_status {
+        outputs[0] = CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(spender_script.clone())
+            .build();
+        outputs.push(
+            CellOutput::new_builder()
+                .capacity(PAYOUT_CAPACITY.pack())
+                .lock(audit_delay_script.clone())
+                .build(),
+        );
+        outputs_data.push(Bytes::from([&receipt_hash[..], &[recreated_status][..]].concat()));
+    }
+
+    if let Some(returned) = params.returned_to_bridge {
+        let bridge_outpoint = context.create_cell(
+            CellOutput::new_builder()
+                .capacity(1000u64.pack())
+                .lock(always_success_script.clone())
+                .type_(Some(bridge_script.clone()).pack())
+                .build(),
+            Bytes::default(),
+        );
+        inputs.insert(
+            0,
+            CellInput::new_builder()
+                .previous_output(bridge_outpoint)
+                .build(),
+        );
+        outputs.insert(
+            0,
+            CellOutput::new_builder()
+                .capacity((1000 + returned).pack())
+                .lock(always_success_script.clone())
+                .type_(Some(bridge_script.clone()).pack())
+                .build(),
+        );
+        outputs_data.insert(0, Bytes::new());
+    }
+
+    let mut tx_builder = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(always_success_dep)
+        .cell_dep(audit_delay_dep);
+
+    // the bridge takes the payout back with its return vetoed payout action
+    if params.returned_to_bridge.is_some() {
+        tx_builder = tx_builder.witness(Bytes::from(vec![3u8]).pack());
+    }
+
+    // header deps are only needed to prove the delay without `since`
+    if let Some((created_at, proof_at)) = params.header_timestamps {
+        let h1 = Header::new_builder()
+            .raw(RawHeader::new_builder().number(1u64.pack()).timestamp(created_at.pack()).build())
+            .build()
+            .into_view();
+        let h2 = Header::new_builder()
+            .raw(RawHeader::new_builder().number(2u64.pack()).timestamp(proof_at.pack()).build())
+            .build()
+            .into_view();
+
+        context.insert_header(h1.clone());
+        context.insert_header(h2.clone());
+        context.link_cell_with_block(audit_delay_outpoint, h1.hash(), 0);
+
+        tx_builder = tx_builder.header_dep(h1.hash()).header_dep(h2.hash());
+    }
+
+    let tx = context.complete_tx(tx_builder.build());
+
+    // run
+    match params.error {
+        None => {
+            context
+                .verify_tx(&tx, MAX_CYCLES)
+                .expect("pass verification");
+        }
+        Some(error) => {
+            let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+            assert_error_eq!(err, error);
+        }
+    }
+}
+
+#[test]
+fn test_header_deps_unlock() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: Some((500, 600)),
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_proof_header_older_than_input() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: Some((600, 400)),
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(5).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_since_block_number_unlock() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_since_block_number_too_early() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 99,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(5).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_since_epoch_unlock() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: relative_epoch(1, 1, 2),
+        since: relative_epoch(1, 3, 4),
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_since_epoch_too_early() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: relative_epoch(1, 1, 2),
+        since: relative_epoch(1, 1, 4),
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(5).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_since_timestamp_for_millisecond_timeout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100_000,
+        since: SINCE_RELATIVE_FLAG | SINCE_METRIC_TIMESTAMP | 100,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_since_wrong_metric() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | SINCE_METRIC_TIMESTAMP | 100,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(8).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_since_absolute() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 100,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(8).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_since_not_spent_with_owner_input() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Stranger,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(6).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_trustee_unrestricted() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: false,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_trustee_returns_to_bridge() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: Some(PAYOUT_CAPACITY),
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_trustee_keeps_vetoed_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(9).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_trustee_returns_part_to_bridge() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: Some(PAYOUT_CAPACITY / 2),
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(9).input_lock_script(2)),
+    });
+}
+
+#[test]
+fn test_trustee_freezes_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: Some(1),
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_trustee_extends_frozen_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(1),
+        recreated_status: Some(1),
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_trustee_releases_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(1),
+        recreated_status: Some(2),
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_trustee_recreates_with_invalid_status() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Trustee,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(1),
+        recreated_status: Some(0),
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(11).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_owner_cannot_claim_frozen_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(1),
+        recreated_status: None,
+        finalized_capacity: None,
+        error: Some(ScriptError::ValidationFailure(10).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_owner_claims_released_payout_early() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: 0,
+        header_timestamps: None,
+        spender: Spender::Owner,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(2),
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_owner_on_later_input() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::OwnerLast,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_owner_as_type_script() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::OwnerType,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: None,
+        error: None,
+    });
+}
+
+#[test]
+fn test_finalize_to_owner() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Stranger,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: Some(PAYOUT_CAPACITY - MAX_FINALIZE_FEE),
+        error: None,
+    });
+}
+
+#[test]
+fn test_finalize_fee_too_high() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Stranger,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: Some(PAYOUT_CAPACITY - MAX_FINALIZE_FEE - 1),
+        error: Some(ScriptError::ValidationFailure(12).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_finalize_too_early() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 99,
+        header_timestamps: None,
+        spender: Spender::Stranger,
+        restricted: true,
+        returned_to_bridge: None,
+        status: None,
+        recreated_status: None,
+        finalized_capacity: Some(PAYOUT_CAPACITY),
+        error: Some(ScriptError::ValidationFailure(5).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_finalize_frozen_payout() {
+    test_audit_delay(AuditDelayTestParams {
+        timeout: SINCE_RELATIVE_FLAG | 100,
+        since: SINCE_RELATIVE_FLAG | 100,
+        header_timestamps: None,
+        spender: Spender::Stranger,
+        restricted: true,
+        returned_to_bridge: None,
+        status: Some(1),
+        recreated_status: None,
+        finalized_capacity: Some(PAYOUT_CAPACITY),
+        error: Some(ScriptError::ValidationFailure(10).input_lock_script(1)),
+    });
+}
+
+#[test]
+fn test_trustee_collects_deposits_with_vetoed_payout() {
+    let mut context = Context::default();
+    let fixture = BridgeFixture::deploy(&mut context);
+
+    let trustee_script = fixture.stand_in_script(&mut context, 1);
+    let owner_script = fixture.stand_in_script(&mut context, 2);
+    let trustee_hash = trustee_script.calc_script_hash().raw_data();
+    let validator_address = rand::thread_rng().gen::<[u8; 20]>();
+    let bridge_script = fixture.bridge_script(
+        &mut context,
+        &[0u8; 32],
+        &trustee_hash,
+        &[&[VALIDATOR_MODE_LIST][..], &validator_address[..]].concat(),
+    );
+    let bridge_hash = bridge_script.calc_script_hash().raw_data();
+
+    // the collected deposit adds as much capacity to the bridge as the vetoed payout holds
+    let data_before = bridge_data(0, 0, 0, checkpoint(0, &[]));
+    let data_after = bridge_data(PAYOUT_CAPACITY, 0, 0, checkpoint(0, &[]));
+    let bridge_output = CellOutput::new_builder()
+        .lock(fixture.always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build();
+    let bridge_capacity = 1000 + bridge_capacity_floor(&bridge_output, data_after.len());
+    let prev_bridge_outpoint = context.create_cell(
+        bridge_output.clone().as_builder().capacity(bridge_capacity.pack()).build(),
+        data_before,
+    );
+    let deposit_lock_script = context
+        .build_script(&fixture.deposit_lock_out_point, Bytes::from([&[0u8; 32][..], &*bridge_hash].concat()))
+        .expect("script");
+    let deposit_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(PAYOUT_CAPACITY.pack())
+            .lock(deposit_lock_script)
+            .build(),
+        Bytes::new(),
+    );
+    let audit_delay_script = context
+        .build_script(
+            &fixture.audit_delay_out_point,
+            Bytes::from(
+                [
+                    &*trustee_hash,
+                    &*owner_script.calc_script_hash().raw_data(),
+                    &TIMEOUT.to_be_bytes()[..],
+                    &*bridge_hash,
+                ]
+                .concat(),
+            ),
+        )
+        .expect("script");
+    let vetoed_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(PAYOUT_CAPACITY.pack())
+            .lock(audit_delay_script)
+            .build(),
+        Bytes::from(rand::thread_rng().gen::<[u8; 32]>().to_vec()),
+    );
+    let trustee_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(trustee_script.clone())
+            .build(),
+        Bytes::default(),
+    );
+
+    let inputs = vec![prev_bridge_outpoint, deposit_outpoint, vetoed_outpoint, trustee_outpoint]
+        .into_iter()
+        .map(|outpoint| CellInput::new_builder().previous_output(outpoint).build());
+    let outputs = vec![
+        bridge_output
+            .as_builder()
+            .capacity((bridge_capacity + PAYOUT_CAPACITY).pack())
+            .build(),
+        // the trustee keeps the vetoed payout
+        CellOutput::new_builder()
+            .capacity((10 + PAYOUT_CAPACITY).pack())
+            .lock(trustee_script)
+            .build(),
+    ];
+    let outputs_data = vec![data_after, Bytes::new()];
+
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_deps(fixture.cell_deps)
+        .witness(Bytes::from(vec![1u8]).pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    check_tx(&context, &tx, Some(ScriptError::ValidationFailure(13).input_lock_script(2)));
+}
+
+// returns a vetoed token payout of 10 to an asset cell holding 100 tokens, the bridge is
+// stood in for, so only the audit delay lock speaks
+fn test_return_vetoed_tokens(returned_amount: u128, to_other_trustee: bool, error: Option<TransactionScriptError>) {
+    let mut context = Context::default();
+    let fixture = BridgeFixture::deploy(&mut context);
+
+    let trustee_script = fixture.stand_in_script(&mut context, 1);
+    let owner_script = fixture.stand_in_script(&mut context, 2);
+    let stranger_script = fixture.stand_in_script(&mut context, 3);
+    let bridge_script = fixture.stand_in_script(&mut context, 4);
+    let token_script = fixture.stand_in_script(&mut context, 5);
+    let trustee_hash = trustee_script.calc_script_hash().raw_data();
+    let bridge_hash = bridge_script.calc_script_hash().raw_data();
+    let asset_lock_script = fixture.bridge_guard_script(&mut context, &bridge_hash, &trustee_hash);
+    let returned_lock_script = if to_other_trustee {
+        fixture.bridge_guard_script(&mut context, &bridge_hash, &stranger_script.calc_script_hash().raw_data())
+    } else {
+        asset_lock_script.clone()
+    };
+    let audit_delay_script = context
+        .build_script(
+            &fixture.audit_delay_out_point,
+            Bytes::from(
+                [
+                    &*trustee_hash,
+                    &*owner_script.calc_script_hash().raw_data(),
+                    &TIMEOUT.to_be_bytes()[..],
+                    &*bridge_hash,
+                ]
+                .concat(),
+            ),
+        )
+        .expect("script");
+
+    let bridge_output = CellOutput::new_builder()
+        .capacity(1000u64.pack())
+        .lock(fixture.always_success_script.clone())
+        .type_(Some(bridge_script).pack())
+        .build();
+    let asset_cell = CellOutput::new_builder()
+        .capacity(PAYOUT_CAPACITY.pack())
+        .lock(asset_lock_script)
+        .type_(Some(token_script.clone()).pack())
+        .build();
+    let prev_bridge_outpoint = context.create_cell(bridge_output.clone(), Bytes::new());
+    let asset_cell_outpoint = context.create_cell(asset_cell.clone(), Bytes::from(100u128.to_le_bytes().to_vec()));
+    let vetoed_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(PAYOUT_CAPACITY.pack())
+            .lock(audit_delay_script)
+            .type_(Some(token_script).pack())
+            .build(),
+        Bytes::from([&10u128.to_le_bytes()[..], &rand::thread_rng().gen::<[u8; 32]>()[..]].concat()),
+    );
+    let trustee_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(trustee_script.clone())
+            .build(),
+        Bytes::default(),
+    );
+
+    let inputs = vec![prev_bridge_outpoint, asset_cell_outpoint, vetoed_outpoint, trustee_outpoint]
+        .into_iter()
+        .map(|outpoint| CellInput::new_builder().previous_output(outpoint).build());
+    let outputs = vec![
+        bridge_output,
+        // the tokens and the capacity of the payout go into the asset cell
+        asset_cell
+            .as_builder()
+            .capacity((2 * PAYOUT_CAPACITY).pack())
+            .lock(returned_lock_script)
+            .build(),
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(trustee_script)
+            .build(),
+    ];
+    let outputs_data = vec![
+        Bytes::new(),
+        Bytes::from((100 + returned_amount).to_le_bytes().to_vec()),
+        Bytes::new(),
+    ];
+
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_deps(fixture.cell_deps)
+        .witness(Bytes::from(vec![3u8]).pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    check_tx(&context, &tx, error);
+}
+
+#[test]
+fn test_trustee_returns_tokens_to_asset_cells() {
+    test_return_vetoed_tokens(10, false, None);
+}
+
+#[test]
+fn test_trustee_returns_part_of_tokens() {
+    test_return_vetoed_tokens(9, false, Some(ScriptError::ValidationFailure(14).input_lock_script(2)));
+}
+
+#[test]
+fn test_trustee_returns_tokens_to_other_trustee() {
+    test_return_vetoed_tokens(10, true, Some(ScriptError::ValidationFailure(14).input_lock_script(2)));
+}
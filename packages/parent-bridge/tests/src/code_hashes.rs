@@ -0,0 +1,49 @@
+use super::*;
+use ckb_tool::ckb_types::{packed::CellOutput, prelude::*};
+
+// written by the parent-bridge build script next to the binaries
+const CODE_HASHES_ARTIFACT: &str = "parent-bridge.code_hashes";
+const HASH_TYPE_DATA: &str = "0";
+
+// each profile is checked on its own, so both have to be built
+fn check_code_hashes(env: TestEnv) {
+    let loader = Loader::with_test_env(env);
+    let mut path = loader.0.clone();
+    path.push(CODE_HASHES_ARTIFACT);
+    let artifact = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("{} not found, build parent-bridge for this profile first", path.display()));
+
+    let mut names = Vec::new();
+    for line in artifact.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields.len(), 3, "malformed line: {}", line);
+        let (name, code_hash, hash_type) = (fields[0], fields[1], fields[2]);
+        names.push(name);
+        // Type ID deployments can't be checked against a binary
+        if hash_type != HASH_TYPE_DATA {
+            continue;
+        }
+        let mut binary_path = loader.0.clone();
+        binary_path.push(name);
+        let binary = fs::read(&binary_path)
+            .unwrap_or_else(|_| panic!("{} not found, build {} for this profile first", binary_path.display(), name));
+        let data_hash = CellOutput::calc_data_hash(&binary);
+        assert_eq!(
+            code_hash,
+            format!("0x{}", hex::encode(data_hash.as_slice())),
+            "parent-bridge was built against a different {}",
+            name
+        );
+    }
+    assert_eq!(names, vec!["deposit-lock", "audit-delay", "bridge-guard"]);
+}
+
+#[test]
+fn test_debug_code_hashes() {
+    check_code_hashes(TestEnv::Debug);
+}
+
+#[test]
+fn test_release_code_hashes() {
+    check_code_hashes(TestEnv::Release);
+}
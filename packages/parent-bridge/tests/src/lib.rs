@@ -1,4 +1,13 @@
-use ckb_tool::ckb_types::bytes::Bytes;
+use ckb_testtool::context::Context;
+use ckb_tool::ckb_hash::new_blake2b;
+use ckb_tool::ckb_types::{
+    bytes::Bytes,
+    core::{Capacity, TransactionView},
+    packed::{CellDep, CellInput, CellOutput, OutPoint, Script},
+    prelude::*,
+};
+use ckb_tool::{ckb_error::assert_error_eq, ckb_script::TransactionScriptError};
+use k256::ecdsa::{recoverable, signature::{Signature, Signer}, SigningKey};
 use std::env;
 use std::fs;
 use std::path::PathBuf;
@@ -7,8 +16,23 @@ use std::str::FromStr;
 #[cfg(test)]
 mod tests;
 mod payout;
+mod audit_delay;
+mod code_hashes;
+mod migration;
+mod assets;
+mod shards;
 
 const TEST_ENV_VAR: &str = "CAPSULE_TEST_ENV";
+pub const MAX_CYCLES: u64 = 100_000_000;
+pub const MIN_DEPOSIT: u64 = 10;
+// an all zero config type hash selects the compiled in helper scripts
+pub const NO_CONFIG: [u8; 32] = [0u8; 32];
+pub const VALIDATOR_MODE_LIST: u8 = 0;
+pub const TIMEOUT: u64 = 100;
+// set in the mode byte, validators are blake160 CKB identities
+pub const IDENTITY_CKB_FLAG: u8 = 0x80;
+// CKB identities sign the ckb hash of the message behind this prefix
+pub const CKB_MESSAGE_PREFIX: &[u8] = b"Nervos Message:";
 
 pub enum TestEnv {
     Debug,
@@ -60,3 +84,147 @@ impl Loader {
         fs::read(path).expect("binary").into()
     }
 }
+
+/// Type ID of a cell created at `output_index` by a transaction spending `first_input`
+pub fn type_id(first_input: &CellInput, output_index: u64) -> [u8; 32] {
+    let mut blake2b = new_blake2b();
+    blake2b.update(first_input.as_slice());
+    blake2b.update(&output_index.to_le_bytes());
+    let mut id = [0u8; 32];
+    blake2b.finalize(&mut id);
+    id
+}
+
+/// Capacity a bridge cell needs after a payout: the capacity it occupies
+pub fn bridge_capacity_floor(bridge_output: &CellOutput, data_len: usize) -> u64 {
+    let data_capacity = Capacity::bytes(data_len).expect("capacity");
+    bridge_output
+        .occupied_capacity(data_capacity)
+        .expect("capacity")
+        .as_u64()
+}
+
+/// Used receipt nonces: high-water mark and the window, bit i marks nonce high_water_mark - 1 - i
+pub fn checkpoint(high_water_mark: u64, used: &[u64]) -> Bytes {
+    let mut window = [0u8; 32];
+    for nonce in used {
+        let position = (high_water_mark - 1 - nonce) as usize;
+        window[position / 8] |= 1 << (position % 8);
+    }
+    Bytes::from([&high_water_mark.to_be_bytes()[..], &window[..]].concat())
+}
+
+/// Bridge cell data: the accounting counters followed by the checkpoint
+pub fn bridge_data(total_deposited: u64, total_withdrawn: u64, payout_count: u64, checkpoint: Bytes) -> Bytes {
+    Bytes::from(
+        [
+            &total_deposited.to_be_bytes()[..],
+            &total_withdrawn.to_be_bytes()[..],
+            &payout_count.to_be_bytes()[..],
+            &checkpoint[..],
+        ]
+        .concat(),
+    )
+}
+
+/// Bridge cell data of a multi-asset bridge: the bridge data followed by the asset type
+/// hashes and their balances
+pub fn with_registry(bridge_data: Bytes, registry: &[([u8; 32], u128)]) -> Bytes {
+    let mut data = bridge_data.to_vec();
+    for (asset, balance) in registry {
+        data.extend_from_slice(&asset[..]);
+        data.extend_from_slice(&balance.to_be_bytes());
+    }
+    Bytes::from(data)
+}
+
+/// The bridge and its helper scripts deployed to a test context
+pub struct BridgeFixture {
+    pub always_success_out_point: OutPoint,
+    pub bridge_out_point: OutPoint,
+    pub audit_delay_out_point: OutPoint,
+    pub bridge_guard_out_point: OutPoint,
+    pub deposit_lock_out_point: OutPoint,
+    pub cell_deps: Vec<CellDep>,
+    pub always_success_script: Script,
+}
+
+impl BridgeFixture {
+    pub fn deploy(context: &mut Context) -> Self {
+        let loader = Loader::default();
+        let always_success_out_point = context.deploy_cell(loader.load_binary("anyone-can-spend"));
+        let bridge_out_point = context.deploy_cell(loader.load_binary("parent-bridge"));
+        let audit_delay_out_point = context.deploy_cell(loader.load_binary("audit-delay"));
+        let bridge_guard_out_point = context.deploy_cell(loader.load_binary("bridge-guard"));
+        let deposit_lock_out_point = context.deploy_cell(loader.load_binary("deposit-lock"));
+
+        let cell_deps = vec![
+            &always_success_out_point,
+            &bridge_out_point,
+            &audit_delay_out_point,
+            &bridge_guard_out_point,
+            &deposit_lock_out_point,
+        ]
+        .into_iter()
+        .map(|out_point| CellDep::new_builder().out_point(out_point.clone()).build())
+        .collect();
+        let always_success_script = context
+            .build_script(&always_success_out_point, Bytes::default())
+            .expect("script");
+
+        BridgeFixture {
+            always_success_out_point,
+            bridge_out_point,
+            audit_delay_out_point,
+            bridge_guard_out_point,
+            deposit_lock_out_point,
+            cell_deps,
+            always_success_script,
+        }
+    }
+
+    /// An always success script told apart by its args, stands in for the trustee, a
+    /// user or a token
+    pub fn stand_in_script(&self, context: &mut Context, id: u8) -> Script {
+        context
+            .build_script(&self.always_success_out_point, Bytes::from(vec![id]))
+            .expect("script")
+    }
+
+    /// Bridge type script without a config cell, `validator_set` starts with the mode byte
+    pub fn bridge_script(&self, context: &mut Context, type_id: &[u8], trustee_hash: &[u8], validator_set: &[u8]) -> Script {
+        let args = [type_id, trustee_hash, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], validator_set].concat();
+        context
+            .build_script(&self.bridge_out_point, Bytes::from(args))
+            .expect("script")
+    }
+
+    /// Bridge guard lock of the bridge cell and its asset cells
+    pub fn bridge_guard_script(&self, context: &mut Context, bridge_hash: &[u8], trustee_hash: &[u8]) -> Script {
+        context
+            .build_script(&self.bridge_guard_out_point, Bytes::from([bridge_hash, trustee_hash].concat()))
+            .expect("script")
+    }
+}
+
+/// Signs like `personal_sign`, r || s || recovery id
+pub fn personal_sign(message: &[u8], priv_key: &SigningKey) -> Vec<u8> {
+    let preamble = format!("\x19Ethereum Signed Message:\n{}", message.len());
+    let sig: recoverable::Signature = priv_key.sign([preamble.as_bytes(), message].concat().as_slice());
+    sig.as_bytes().to_vec()
+}
+
+/// Runs the transaction, it has to pass or fail with `error`
+pub fn check_tx(context: &Context, tx: &TransactionView, error: Option<TransactionScriptError>) {
+    match error {
+        None => {
+            context
+                .verify_tx(tx, MAX_CYCLES)
+                .expect("pass verification");
+        }
+        Some(error) => {
+            let err = context.verify_tx(tx, MAX_CYCLES).unwrap_err();
+            assert_error_eq!(err, error);
+        }
+    }
+}
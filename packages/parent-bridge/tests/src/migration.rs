@@ -0,0 +1,337 @@
+use super::*;
+use crate::payout::get_val_keys;
+use ckb_testtool::context::Context;
+use ckb_tool::ckb_hash::blake2b_256;
+use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::ckb_script::{TransactionScriptError, ScriptError};
+use k256::{ecdsa::SigningKey, elliptic_curve::sec1::ToEncodedPoint};
+use sha3::{Digest, Keccak256};
+
+const BRIDGE_CAPACITY: u64 = 1000;
+// relative block number since
+const DELAY: u64 = 0x8000_0000_0000_0064;
+
+fn sign_migration(
+    bridge_hash: &[u8],
+    target_hash: &[u8],
+    target_lock_hash: &[u8],
+    delay: u64,
+    priv_key: &SigningKey,
+    ckb_signature: bool,
+) -> Vec<u8> {
+    let message = [bridge_hash, target_hash, target_lock_hash, &delay.to_be_bytes()[..]].concat();
+    if ckb_signature {
+        // like ckb-cli, sign the ckb hash directly
+        let digest = blake2b_256([CKB_MESSAGE_PREFIX, &message[..]].concat());
+        let secret = secp256k1::SecretKey::parse_slice(&priv_key.to_bytes()).unwrap();
+        let (sig, recovery_id) = secp256k1::sign(&secp256k1::Message::parse(&digest), &secret);
+        return [&sig.serialize()[..], &[recovery_id.serialize()][..]].concat();
+    }
+    personal_sign(&message, priv_key)
+}
+
+struct MigrationTestParams {
+    trustee_signs: bool,
+    // the migration target runs the bridge code instead of a new version
+    target_is_bridge_code: bool,
+    target_version: u8,
+    target_capacity: u64,
+    // defaults to the hash of the bridge data
+    target_data: Option<Bytes>,
+    // validators sign a different target
+    sign_other_target: bool,
+    // validators sign a different lock for the target
+    sign_other_lock: bool,
+    // token balance in the registry, none if the bridge holds no tokens
+    token_balance: Option<u128>,
+    delay: u64,
+    since: u64,
+    // validators are blake160 identities
+    ckb_identity: bool,
+    // the validator signs the ckb hash instead of like personal_sign
+    ckb_signature: bool,
+    error: Option<TransactionScriptError>,
+}
+
+impl Default for MigrationTestParams {
+    fn default() -> Self {
+        MigrationTestParams {
+            trustee_signs: true,
+            target_is_bridge_code: false,
+            target_version: 1,
+            target_capacity: BRIDGE_CAPACITY,
+            target_data: None,
+            sign_other_target: false,
+            sign_other_lock: false,
+            token_balance: None,
+            delay: 0,
+            since: 0,
+            ckb_identity: false,
+            ckb_signature: false,
+            error: None,
+        }
+    }
+}
+
+fn test_migration(params: MigrationTestParams) {
+    let mut context = Context::default();
+    let fixture = BridgeFixture::deploy(&mut context);
+
+    // make our script objects
+    let (priv_key, pub_key) = get_val_keys();
+    let validator_address = if params.ckb_identity {
+        blake2b_256(pub_key.to_encoded_point(true).as_bytes())[..20].to_vec()
+    } else {
+        Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..].to_vec()
+    };
+    let validator_mode = if params.ckb_identity {
+        VALIDATOR_MODE_LIST | IDENTITY_CKB_FLAG
+    } else {
+        VALIDATOR_MODE_LIST
+    };
+    let always_success_script = fixture.always_success_script.clone();
+    let trustee_script = fixture.stand_in_script(&mut context, 1);
+    let stranger_script = fixture.stand_in_script(&mut context, 2);
+    let bridge_script = fixture.bridge_script(
+        &mut context,
+        &[0u8; 32],
+        &trustee_script.calc_script_hash().raw_data(),
+        &[&[validator_mode][..], &validator_address[..]].concat(),
+    );
+    // the target args start with the version byte
+    let target_code = if params.target_is_bridge_code {
+        &fixture.bridge_out_point
+    } else {
+        &fixture.always_success_out_point
+    };
+    let target_script = context
+        .build_script(target_code, Bytes::from(vec![params.target_version, 7u8]))
+        .expect("script");
+    let other_target_script = context
+        .build_script(&fixture.always_success_out_point, Bytes::from(vec![params.target_version, 8u8]))
+        .expect("script");
+    // the migrated bridge is guarded for the target and the trustee
+    let target_lock_script = fixture.bridge_guard_script(
+        &mut context,
+        &target_script.calc_script_hash().raw_data(),
+        &trustee_script.calc_script_hash().raw_data(),
+    );
+
+    // bridge witness
+    let signed_target = if params.sign_other_target {
+        &other_target_script
+    } else {
+        &target_script
+    };
+    let signed_lock = if params.sign_other_lock {
+        &always_success_script
+    } else {
+        &target_lock_script
+    };
+    let sig = sign_migration(
+        &bridge_script.calc_script_hash().raw_data(),
+        &signed_target.calc_script_hash().raw_data(),
+        &signed_lock.calc_script_hash().raw_data(),
+        params.delay,
+        &priv_key,
+        params.ckb_signature,
+    );
+    let bridge_witness = Bytes::from(
+        [
+            &[4u8][..],
+            &params.delay.to_be_bytes()[..],
+            &sig[..],
+        ]
+        .concat(),
+    );
+
+    // input outpoints
+    let registry: Vec<([u8; 32], u128)> = params.token_balance.into_iter().map(|balance| ([3u8; 32], balance)).collect();
+    let bridge_data = with_registry(bridge_data(0, 0, 1, checkpoint(1, &[0])), &registry);
+    let prev_bridge_output = CellOutput::new_builder()
+        .capacity(BRIDGE_CAPACITY.pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build();
+    let consent_output = CellOutput::new_builder()
+        .capacity(10u64.pack())
+        .lock(if params.trustee_signs {
+            trustee_script.clone()
+        } else {
+            stranger_script.clone()
+        })
+        .build();
+
+    let prev_bridge_outpoint = context.create_cell(prev_bridge_output, bridge_data.clone());
+    let consent_outpoint = context.create_cell(consent_output, Bytes::default());
+
+    let inputs = vec![
+        // bridge input
+        CellInput::new_builder()
+            .previous_output(prev_bridge_outpoint)
+            .since(params.since.pack())
+            .build(),
+        // trustee input
+        CellInput::new_builder()
+            .previous_output(consent_outpoint)
+            .build(),
+    ];
+
+    let outputs = vec![
+        // migrated bridge output
+        CellOutput::new_builder()
+            .capacity(params.target_capacity.pack())
+            .lock(target_lock_script)
+            .type_(Some(target_script.clone()).pack())
+            .build(),
+    ];
+
+    let witnesses = vec![bridge_witness, Bytes::new()];
+
+    let commitment = Bytes::from(Vec::from(&Keccak256::digest(&bridge_data[..])[..]));
+    let outputs_data = vec![params.target_data.unwrap_or(commitment)];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_deps(fixture.cell_deps)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    check_tx(&context, &tx, params.error);
+}
+
+#[test]
+fn test_migration_success() {
+    test_migration(MigrationTestParams::default());
+}
+
+#[test]
+fn test_migration_not_signed_by_trustee() {
+    test_migration(MigrationTestParams {
+        trustee_signs: false,
+        error: Some(ScriptError::ValidationFailure(18).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_to_same_code() {
+    test_migration(MigrationTestParams {
+        target_is_bridge_code: true,
+        error: Some(ScriptError::ValidationFailure(39).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_wrong_version() {
+    test_migration(MigrationTestParams {
+        target_version: 0,
+        error: Some(ScriptError::ValidationFailure(40).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_signed_for_other_target() {
+    test_migration(MigrationTestParams {
+        sign_other_target: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_capacity_computed_incorrectly() {
+    test_migration(MigrationTestParams {
+        target_capacity: BRIDGE_CAPACITY - 1,
+        error: Some(ScriptError::ValidationFailure(41).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_wrong_receipt_commitment() {
+    test_migration(MigrationTestParams {
+        target_data: Some(Bytes::new()),
+        error: Some(ScriptError::ValidationFailure(42).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_after_delay() {
+    test_migration(MigrationTestParams {
+        delay: DELAY,
+        since: DELAY,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_delay_not_passed() {
+    test_migration(MigrationTestParams {
+        delay: DELAY,
+        since: 0,
+        error: Some(ScriptError::ValidationFailure(43).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_ckb_identity() {
+    test_migration(MigrationTestParams {
+        ckb_identity: true,
+        ckb_signature: true,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_ckb_identity_signed_like_ethereum() {
+    test_migration(MigrationTestParams {
+        ckb_identity: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_ethereum_identity_signed_like_ckb() {
+    test_migration(MigrationTestParams {
+        ckb_signature: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_signed_for_other_lock() {
+    test_migration(MigrationTestParams {
+        sign_other_lock: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_with_tokens() {
+    test_migration(MigrationTestParams {
+        token_balance: Some(100),
+        error: Some(ScriptError::ValidationFailure(62).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_migration_with_empty_token_balance() {
+    test_migration(MigrationTestParams {
+        token_balance: Some(0),
+        ..Default::default()
+    });
+}
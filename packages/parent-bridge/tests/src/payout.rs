@@ -1,6 +1,7 @@
 use super::*;
 use ckb_testtool::context::Context;
-use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::ckb_hash::blake2b_256;
+use ckb_tool::ckb_types::{bytes::Bytes, core::{ScriptHashType, TransactionBuilder}, packed::*, prelude::*};
 use ckb_tool::{ckb_error::assert_error_eq, ckb_script::{TransactionScriptError, ScriptError}};
 use hex::FromHex;
 use k256::{
@@ -10,19 +11,37 @@ use k256::{
         SigningKey, VerifyingKey,
     },
     elliptic_curve::sec1::ToEncodedPoint,
+    FieldBytes, ProjectivePoint, Scalar,
 };
+use sha2::Sha256;
 use rand::Rng;
 use rand_core::OsRng;
 use sha3::{Digest, Keccak256}; // requires 'getrandom' feature
 
-const MAX_CYCLES: u64 = 100_000_000;
-
-fn get_val_keys() -> (SigningKey, VerifyingKey) {
+const VALIDATOR_MODE_MERKLE: u8 = 1;
+const VALIDATOR_MODE_WEIGHTED_LIST: u8 = 2;
+const VALIDATOR_MODE_WEIGHTED_MERKLE: u8 = 3;
+const VALIDATOR_MODE_SCHNORR: u8 = 4;
+const EIP712_FLAG: u8 = 0x40;
+const CHAIN_ID: u64 = 1;
+const PAYOUT_AMOUNT: u64 = 10;
+// the ckb types used here predate the data1 hash type
+const HASH_TYPE_DATA1: u8 = 2;
+
+pub fn get_val_keys() -> (SigningKey, VerifyingKey) {
     let signing_key = SigningKey::random(&mut OsRng); // Serialize with `::to_bytes()`
     let verify_key = signing_key.verify_key();
     (signing_key, verify_key)
 }
 
+fn eth_address(pub_key: &VerifyingKey) -> Vec<u8> {
+    Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..].to_vec()
+}
+
+fn blake160_identity(pub_key: &VerifyingKey) -> Vec<u8> {
+    blake2b_256(pub_key.to_encoded_point(true).as_bytes())[..20].to_vec()
+}
+
 fn gen_receipt(amount: u64, lock_hash: [u8; 32], tx_hash: [u8; 32]) -> [u8; 128] {
     let mut receipt: [u8; 128] = [0; 128];
 
@@ -41,18 +60,250 @@ fn gen_receipt(amount: u64, lock_hash: [u8; 32], tx_hash: [u8; 32]) -> [u8; 128]
     receipt
 }
 
+fn with_nonce(mut receipt: [u8; 128], nonce: u64) -> [u8; 128] {
+    receipt[32..40].copy_from_slice(&nonce.to_be_bytes());
+    receipt
+}
+
+fn with_expiry(mut receipt: [u8; 128], expiry: u64) -> [u8; 128] {
+    receipt[40..48].copy_from_slice(&expiry.to_be_bytes());
+    receipt
+}
+
+fn with_fee(mut receipt: [u8; 128], fee: u64) -> [u8; 128] {
+    receipt[48..56].copy_from_slice(&fee.to_be_bytes());
+    receipt
+}
+
+fn with_asset(mut receipt: [u8; 128], asset: [u8; 32]) -> [u8; 128] {
+    receipt[0..32].copy_from_slice(&asset);
+    receipt
+}
+
 fn sign_receipt(receipt: [u8; 128], priv_key: SigningKey) -> recoverable::Signature {
     let preamble: &[u8] = b"\x19Ethereum Signed Message:\n128";
     let msg: Vec<u8> = [preamble, &receipt[..]].concat();
     priv_key.sign(msg.as_slice())
 }
 
-struct PayoutTestParams<'a> {
+// like Neuron, sign the ckb hash of the prefixed receipt
+fn sign_receipt_ckb(receipt: [u8; 128], priv_key: SigningKey) -> recoverable::Signature {
+    let digest = blake2b_256([CKB_MESSAGE_PREFIX, &receipt[..]].concat());
+    let secret = secp256k1::SecretKey::parse_slice(&priv_key.to_bytes()).unwrap();
+    let (sig, recovery_id) = secp256k1::sign(&secp256k1::Message::parse(&digest), &secret);
+    recoverable::Signature::from_bytes(&[&sig.serialize()[..], &[recovery_id.serialize()][..]].concat()).unwrap()
+}
+
+fn abi_encode_u64(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+// 0x1901 || domain separator || hash of the Withdrawal struct, its keccak hash is the typed data hash
+fn withdrawal_message(receipt: [u8; 128], bridge_id: [u8; 32], chain_id: u64) -> Vec<u8> {
+    let domain_separator = Keccak256::digest(
+        &[
+            &Keccak256::digest(b"EIP712Domain(string name,string version,uint256 chainId)")[..],
+            &Keccak256::digest(b"Nervos Bridge")[..],
+            &Keccak256::digest(b"1")[..],
+            &abi_encode_u64(chain_id)[..],
+        ]
+        .concat(),
+    );
+    let withdrawal_type =
+        b"Withdrawal(bytes32 bridgeId,bytes32 txHash,bytes32 receiver,uint256 amount,uint256 chainId,uint256 nonce,uint256 expiry,uint256 fee,bytes32 asset)";
+    let mut amount = [0u8; 8];
+    amount.copy_from_slice(&receipt[56..64]);
+    let mut nonce = [0u8; 8];
+    nonce.copy_from_slice(&receipt[32..40]);
+    let mut expiry = [0u8; 8];
+    expiry.copy_from_slice(&receipt[40..48]);
+    let mut fee = [0u8; 8];
+    fee.copy_from_slice(&receipt[48..56]);
+    let struct_hash = Keccak256::digest(
+        &[
+            &Keccak256::digest(&withdrawal_type[..])[..],
+            &bridge_id[..],
+            &receipt[96..128],
+            &receipt[64..96],
+            &abi_encode_u64(u64::from_be_bytes(amount))[..],
+            &abi_encode_u64(chain_id)[..],
+            &abi_encode_u64(u64::from_be_bytes(nonce))[..],
+            &abi_encode_u64(u64::from_be_bytes(expiry))[..],
+            &abi_encode_u64(u64::from_be_bytes(fee))[..],
+            &receipt[0..32],
+        ]
+        .concat(),
+    );
+    [&b"\x19\x01"[..], &domain_separator[..], &struct_hash[..]].concat()
+}
+
+// like eth_signTypedData, the key signs the keccak hash of the message
+fn sign_withdrawal(receipt: [u8; 128], bridge_id: [u8; 32], chain_id: u64, priv_key: SigningKey) -> recoverable::Signature {
+    priv_key.sign(&withdrawal_message(receipt, bridge_id, chain_id))
+}
+
+enum ConfigCell {
+    // compiled in helper scripts
+    Compiled,
+    // config cell listing the helper scripts under test
+    Helpers,
+    // config cell naming a different audit delay binary
+    UnknownAuditDelay,
+    // config cell with a truncated entry
+    InvalidData,
+    // config type hash in the args, but no cell dep
+    NotInCellDeps,
+    // config cell with an ordinary type script instead of a Type ID
+    NotTypeId,
+}
+
+// code hash of the Type ID system script, "TYPE_ID" right aligned
+const TYPE_ID_CODE_HASH: [u8; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, b'T', b'Y', b'P', b'E', b'_', b'I', b'D',
+];
+
+fn config_data(deposit_lock: &Byte32, audit_delay: &Byte32, bridge_guard: &Byte32) -> Bytes {
+    let data_hash_type: &[u8] = &[0u8];
+    Bytes::from(
+        [
+            deposit_lock.as_slice(),
+            data_hash_type,
+            audit_delay.as_slice(),
+            data_hash_type,
+            bridge_guard.as_slice(),
+            data_hash_type,
+        ]
+        .concat(),
+    )
+}
+
+struct MerkleParams {
+    threshold: u64,
+    // the signing validator is one of the leaves
+    signer_in_set: bool,
+    // the same approval is repeated in the witness
+    repeat_approval: bool,
+    // stake of the signer in a weighted tree, every other leaf weighs 1
+    signer_weight: Option<u64>,
+    // weight the signer claims in the witness, defaults to the signer weight
+    claimed_weight: Option<u64>,
+}
+
+enum SigEncoding {
+    // r || s || recovery id 0 / 1, as k256 produces it
+    RecoveryId,
+    // v = 27 / 28 like Bridge.sol
+    EthereumV,
+    // (r, n - s) with the flipped recovery id, valid but not canonical
+    HighS,
+    InvalidV,
+}
+
+fn encode_signature(sig: &[u8], encoding: &SigEncoding) -> Vec<u8> {
+    let mut sig = sig.to_vec();
+    match encoding {
+        SigEncoding::RecoveryId => {}
+        SigEncoding::EthereumV => sig[64] += 27,
+        SigEncoding::HighS => {
+            let s = Scalar::from_bytes_reduced(FieldBytes::from_slice(&sig[32..64]));
+            sig[32..64].copy_from_slice(&(-s).to_bytes());
+            sig[64] ^= 1;
+        }
+        SigEncoding::InvalidV => sig[64] = 29,
+    }
+    sig
+}
+
+struct SchnorrParams {
+    // signed by the aggregate key in the args rather than another key
+    signed_by_aggregate_key: bool,
+    // signature is cut short by one byte
+    truncated: bool,
+}
+
+fn tagged_hash(tag: &[u8], data: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag);
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Sha256::digest(&[&tag_hash[..], &tag_hash[..], data].concat()));
+    hash
+}
+
+/// x coordinate and y parity of secret * G
+fn x_only(secret: &Scalar) -> ([u8; 32], bool) {
+    let point = (ProjectivePoint::generator() * secret).to_affine().to_encoded_point(false);
+    let bytes = point.as_bytes();
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&bytes[1..33]);
+    (x, bytes[64] & 1 == 1)
+}
+
+/// BIP340 key pair, the secret is negated if needed so the key has an even y
+fn schnorr_keys() -> (Scalar, [u8; 32]) {
+    let secret = Scalar::from_bytes_reduced(&get_val_keys().0.to_bytes());
+    let (key, odd) = x_only(&secret);
+    (if odd { -secret } else { secret }, key)
+}
+
+fn schnorr_sign(secret: &Scalar, msg: &[u8]) -> [u8; 64] {
+    let (key, _) = x_only(secret);
+    let nonce = Scalar::from_bytes_reduced(&get_val_keys().0.to_bytes());
+    let (r, odd) = x_only(&nonce);
+    let nonce = if odd { -nonce } else { nonce };
+    let challenge = tagged_hash(b"BIP0340/challenge", &[&r[..], &key[..], msg].concat());
+    let e = Scalar::from_bytes_reduced(FieldBytes::from_slice(&challenge));
+    let s = nonce + e * secret;
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&r);
+    sig[32..].copy_from_slice(&s.to_bytes());
+    sig
+}
+
+struct WeightParams {
+    threshold: u64,
+    signer_weight: u64,
+    // stake of a second validator that doesn't sign
+    other_weight: u64,
+}
+
+fn merkle_leaf(address: &[u8]) -> [u8; 32] {
+    let mut leaf = [0u8; 32];
+    leaf.copy_from_slice(&Keccak256::digest(address));
+    leaf
+}
+
+fn merkle_parent(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let pair = if a <= b { [&a[..], &b[..]].concat() } else { [&b[..], &a[..]].concat() };
+    merkle_leaf(&pair)
+}
+
+/// Root and the proof of the first leaf, an odd node is carried up unchanged
+fn merkle_root_and_proof(leaves: Vec<[u8; 32]>) -> ([u8; 32], Vec<u8>) {
+    let mut layer = leaves;
+    let mut index = 0;
+    let mut proof = Vec::new();
+    while layer.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < layer.len() {
+            proof.extend_from_slice(&layer[sibling]);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| if pair.len() == 2 { merkle_parent(&pair[0], &pair[1]) } else { pair[0] })
+            .collect();
+        index /= 2;
+    }
+    (layer[0], proof)
+}
+
+struct PayoutTestParams {
     audit_delay_trustee_lock_hash: [u8; 32],
     audit_delay_owner_lock_hash: [u8; 32],
     audit_delay_payout_amount: u64,
-    bridge_state_id: [u8; 36],
-    bridge_validators: Vec<&'a [u8]>,
+    bridge_state_id: [u8; 32],
+    bridge_validators: Vec<Vec<u8>>,
     bridge_trustee: [u8; 32],
     sig: recoverable::Signature,
     receipt: [u8; 128],
@@ -63,9 +314,86 @@ struct PayoutTestParams<'a> {
     bridge_data_after: Bytes,
     funding_amount: u64,
     timeout: u64,
+    // defaults to the hash of the bridge type script
+    audit_delay_bridge_hash: Option<[u8; 32]>,
+    // defaults to the hash of the receipt
+    payout_data: Option<Bytes>,
+    // defaults to the hash type the audit delay binary was deployed with
+    audit_delay_hash_type: Option<Byte>,
+    config: ConfigCell,
+    // validators committed as a merkle root instead of a list
+    merkle: Option<MerkleParams>,
+    // validators listed with stake weights
+    weights: Option<WeightParams>,
+    // validators approve with one signature of their aggregate key
+    schnorr: Option<SchnorrParams>,
+    sig_encoding: SigEncoding,
+    // receipts are signed as EIP-712 typed data for this chain id
+    eip712_chain_id: Option<u64>,
+    // validators are blake160 CKB identities
+    ckb_identity: bool,
+    // add the capacity the bridge cell occupies to the capacities under test
+    reserve_capacity: bool,
+    // timestamps of the header the bridge cell was created in and of the proof header
+    header_timestamps: Option<(u64, u64)>,
     error: Option<TransactionScriptError>,
 }
 
+// a receipt of PAYOUT_AMOUNT for a random owner
+fn payout_receipt() -> [u8; 128] {
+    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
+    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
+    gen_receipt(PAYOUT_AMOUNT, receipt_owner_lock_hash, receipt_tx_hash)
+}
+
+impl Default for PayoutTestParams {
+    fn default() -> Self {
+        PayoutTestParams::signed(payout_receipt())
+    }
+}
+
+impl PayoutTestParams {
+    /// Pays out a receipt of PAYOUT_AMOUNT with the lowest nonce to its owner, the only
+    /// validator signs it
+    fn signed(receipt: [u8; 128]) -> Self {
+        let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
+        let mut receipt_owner_lock_hash = [0u8; 32];
+        receipt_owner_lock_hash.copy_from_slice(&receipt[64..96]);
+        let (priv_key, pub_key) = get_val_keys();
+
+        PayoutTestParams {
+            audit_delay_trustee_lock_hash: trustee_lock_hash,
+            audit_delay_owner_lock_hash: receipt_owner_lock_hash,
+            audit_delay_payout_amount: PAYOUT_AMOUNT,
+            bridge_state_id: [0u8; 32],
+            bridge_validators: vec![eth_address(&pub_key)],
+            bridge_trustee: trustee_lock_hash,
+            sig: sign_receipt(receipt, priv_key),
+            receipt: receipt,
+            bridge_after_capacity: 90,
+            bridge_before_capacity: 100,
+            change_capacity: 8,
+            bridge_data_before: Bytes::default(),
+            bridge_data_after: bridge_data(0, PAYOUT_AMOUNT, 1, checkpoint(1, &[0])),
+            funding_amount: 10,
+            timeout: 100,
+            audit_delay_bridge_hash: None,
+            payout_data: None,
+            audit_delay_hash_type: None,
+            config: ConfigCell::Compiled,
+            merkle: None,
+            weights: None,
+            schnorr: None,
+            sig_encoding: SigEncoding::RecoveryId,
+            eip712_chain_id: None,
+            ckb_identity: false,
+            reserve_capacity: true,
+            header_timestamps: None,
+            error: None,
+        }
+    }
+}
+
 fn test_payout(params: PayoutTestParams) {
     let mut context = Context::default();
 
@@ -77,7 +405,7 @@ fn test_payout(params: PayoutTestParams) {
     // deploy binaries to cells
     let always_success_out_point = context.deploy_cell(always_success_bin);
     let bridge_out_point = context.deploy_cell(bridge_bin);
-    let audit_delay_out_point = context.deploy_cell(audit_delay_bin);
+    let audit_delay_out_point = context.deploy_cell(audit_delay_bin.clone());
 
     // make dep objects for our contracts
     let always_success_dep = CellDep::new_builder()
@@ -92,15 +420,43 @@ fn test_payout(params: PayoutTestParams) {
 
     // always success args
     let always_success_args = Bytes::default();
-    // audit delay args
-    let audit_delay_args = Bytes::from(
-        [
-            &params.audit_delay_trustee_lock_hash[..],
-            &params.audit_delay_owner_lock_hash[..],
-            &params.timeout.to_be_bytes()[..],
-        ]
-        .concat(),
-    );
+    // config cell
+    let mut config_hash = NO_CONFIG;
+    let mut config_deps = Vec::new();
+    if !matches!(params.config, ConfigCell::Compiled) {
+        let deposit_lock_hash = CellOutput::calc_data_hash(&Loader::default().load_binary("deposit-lock"));
+        let bridge_guard_hash = CellOutput::calc_data_hash(&Loader::default().load_binary("bridge-guard"));
+        let audit_delay_hash = match params.config {
+            ConfigCell::UnknownAuditDelay => CellOutput::calc_data_hash(&[0u8]),
+            _ => CellOutput::calc_data_hash(&audit_delay_bin),
+        };
+        let mut data = config_data(&deposit_lock_hash, &audit_delay_hash, &bridge_guard_hash);
+        if matches!(params.config, ConfigCell::InvalidData) {
+            data.truncate(data.len() - 1);
+        }
+        let config_type_script = match params.config {
+            ConfigCell::NotTypeId => context
+                .build_script(&always_success_out_point, Bytes::from(vec![9u8]))
+                .expect("script"),
+            _ => Script::new_builder()
+                .code_hash(TYPE_ID_CODE_HASH.pack())
+                .hash_type(ScriptHashType::Type.into())
+                .args(Bytes::from(rand::thread_rng().gen::<[u8; 32]>().to_vec()).pack())
+                .build(),
+        };
+        config_hash.copy_from_slice(&config_type_script.calc_script_hash().raw_data());
+        let config_out_point = context.create_cell(
+            CellOutput::new_builder()
+                .capacity(1000u64.pack())
+                .lock(context.build_script(&always_success_out_point, Bytes::default()).expect("script"))
+                .type_(Some(config_type_script).pack())
+                .build(),
+            data,
+        );
+        if !matches!(params.config, ConfigCell::NotInCellDeps) {
+            config_deps.push(CellDep::new_builder().out_point(config_out_point).build());
+        }
+    }
     // bridge args
     let flat_validators = params
         .bridge_validators
@@ -108,11 +464,73 @@ fn test_payout(params: PayoutTestParams) {
         .map(|val| val.to_vec())
         .flatten()
         .collect::<Vec<u8>>();
+    let signer = &params.bridge_validators[0][..];
+    // weighted leaves are keccak(address || weight)
+    let signer_weight = params.merkle.as_ref().and_then(|merkle| merkle.signer_weight);
+    let leaf_data = |address: &[u8]| match signer_weight {
+        Some(_) => [address, &1u64.to_be_bytes()[..]].concat(),
+        None => address.to_vec(),
+    };
+    let first_leaf = match &params.merkle {
+        Some(merkle) if !merkle.signer_in_set => leaf_data(&rand::thread_rng().gen::<[u8; 20]>()),
+        _ => match signer_weight {
+            Some(weight) => [signer, &weight.to_be_bytes()[..]].concat(),
+            None => signer.to_vec(),
+        },
+    };
+    let leaves = vec![
+        merkle_leaf(&first_leaf),
+        merkle_leaf(&leaf_data(&rand::thread_rng().gen::<[u8; 20]>())),
+        merkle_leaf(&leaf_data(&rand::thread_rng().gen::<[u8; 20]>())),
+    ];
+    let (merkle_root, merkle_proof) = merkle_root_and_proof(leaves);
+    let validator_set = match (&params.merkle, &params.weights) {
+        (Some(merkle), _) => [
+            &[if signer_weight.is_some() { VALIDATOR_MODE_WEIGHTED_MERKLE } else { VALIDATOR_MODE_MERKLE }][..],
+            &merkle_root[..],
+            &merkle.threshold.to_be_bytes()[..],
+        ]
+        .concat(),
+        (None, Some(weights)) => [
+            &[VALIDATOR_MODE_WEIGHTED_LIST][..],
+            &weights.threshold.to_be_bytes()[..],
+            signer,
+            &weights.signer_weight.to_be_bytes()[..],
+            &rand::thread_rng().gen::<[u8; 20]>()[..],
+            &weights.other_weight.to_be_bytes()[..],
+        ]
+        .concat(),
+        (None, None) => [&[VALIDATOR_MODE_LIST][..], flat_validators.as_slice()].concat(),
+    };
+    // aggregate key of the validators and a key outside the set
+    let (aggregate_secret, aggregate_key) = schnorr_keys();
+    let (other_secret, _) = schnorr_keys();
+    let validator_set = match &params.schnorr {
+        Some(_) => [&[VALIDATOR_MODE_SCHNORR][..], &aggregate_key[..]].concat(),
+        None => validator_set,
+    };
+    // the chain id precedes the mode's data
+    let validator_set = match params.eip712_chain_id {
+        Some(chain_id) => [
+            &[validator_set[0] | EIP712_FLAG][..],
+            &chain_id.to_be_bytes()[..],
+            &validator_set[1..],
+        ]
+        .concat(),
+        None => validator_set,
+    };
+    let validator_set = if params.ckb_identity {
+        [&[validator_set[0] | IDENTITY_CKB_FLAG][..], &validator_set[1..]].concat()
+    } else {
+        validator_set
+    };
     let bridge_args = Bytes::from(
         [
             &params.bridge_state_id[..],
             &params.bridge_trustee[..],
-            flat_validators.as_slice(),
+            &MIN_DEPOSIT.to_be_bytes()[..],
+            &config_hash[..],
+            validator_set.as_slice(),
         ]
         .concat(),
     );
@@ -124,13 +542,54 @@ fn test_payout(params: PayoutTestParams) {
     let bridge_script = context
         .build_script(&bridge_out_point, bridge_args)
         .expect("script");
+    // audit delay args
+    let mut bridge_script_hash = [0u8; 32];
+    bridge_script_hash.copy_from_slice(&bridge_script.calc_script_hash().raw_data());
+    let audit_delay_args = Bytes::from(
+        [
+            &params.audit_delay_trustee_lock_hash[..],
+            &params.audit_delay_owner_lock_hash[..],
+            &params.timeout.to_be_bytes()[..],
+            &params.audit_delay_bridge_hash.unwrap_or(bridge_script_hash)[..],
+        ]
+        .concat(),
+    );
     let audit_delay_script = context
         .build_script(&audit_delay_out_point, audit_delay_args)
         .expect("script");
+    let audit_delay_script = match params.audit_delay_hash_type {
+        Some(hash_type) => audit_delay_script.as_builder().hash_type(hash_type).build(),
+        None => audit_delay_script,
+    };
 
     // bridge witness
     let action_byte = Bytes::from(Vec::from_hex("00").unwrap());
-    let signature = Bytes::from(Vec::from(params.sig.as_bytes()));
+    let signature = match &params.merkle {
+        None => Bytes::from(encode_signature(params.sig.as_bytes(), &params.sig_encoding)),
+        Some(merkle) => {
+            // address (and weight), signature, proof length and proof
+            let claimed_weight = merkle.claimed_weight.or(merkle.signer_weight);
+            let approval = [
+                signer,
+                &claimed_weight.map(|weight| weight.to_be_bytes().to_vec()).unwrap_or_default()[..],
+                params.sig.as_bytes(),
+                &[(merkle_proof.len() / 32) as u8][..],
+                &merkle_proof[..],
+            ]
+            .concat();
+            let copies = if merkle.repeat_approval { 2 } else { 1 };
+            Bytes::from(approval.repeat(copies))
+        }
+    };
+    let signature = match &params.schnorr {
+        Some(schnorr) => {
+            let secret = if schnorr.signed_by_aggregate_key { &aggregate_secret } else { &other_secret };
+            let sig = schnorr_sign(secret, &Keccak256::digest(&params.receipt[..]));
+            let sig_len = if schnorr.truncated { 63 } else { 64 };
+            Bytes::from(sig[..sig_len].to_vec())
+        }
+        None => signature,
+    };
     let bridge_witness = Bytes::from(
         [
             action_byte,
@@ -140,9 +599,20 @@ fn test_payout(params: PayoutTestParams) {
         .concat(),
     );
 
+    // the bridge capacities under test come on top of what the bridge cell needs itself
+    let bridge_floor = if params.reserve_capacity {
+        let bridge_output = CellOutput::new_builder()
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build();
+        bridge_capacity_floor(&bridge_output, params.bridge_data_after.len())
+    } else {
+        0
+    };
+
     // input outpoints
     let prev_bridge_output = CellOutput::new_builder()
-        .capacity(params.bridge_before_capacity.pack())
+        .capacity((params.bridge_before_capacity + bridge_floor).pack())
         .lock(always_success_script.clone())
         .type_(Some(bridge_script.clone()).pack())
         .build();
@@ -157,7 +627,7 @@ fn test_payout(params: PayoutTestParams) {
     let inputs = vec![
         // bridge input
         CellInput::new_builder()
-            .previous_output(prev_bridge_outpoint)
+            .previous_output(prev_bridge_outpoint.clone())
             .build(),
         // funding input
         CellInput::new_builder()
@@ -168,7 +638,7 @@ fn test_payout(params: PayoutTestParams) {
     let outputs = vec![
         // bridge output
         CellOutput::new_builder()
-            .capacity(params.bridge_after_capacity.pack())
+            .capacity((params.bridge_after_capacity + bridge_floor).pack())
             .lock(always_success_script.clone())
             .type_(Some(bridge_script.clone()).pack())
             .build(),
@@ -186,24 +656,43 @@ fn test_payout(params: PayoutTestParams) {
 
     let witnesses = vec![bridge_witness, Bytes::new(), Bytes::new()];
 
+    let receipt_hash = Bytes::from(Vec::from(&Keccak256::digest(&params.receipt[..])[..]));
     let outputs_data = vec![
         params.bridge_data_after,
-        // Bytes::from(Vec::from(&Keccak256::digest(&params.receipt[..])[..])),
-        Bytes::new(),
+        params.payout_data.unwrap_or(receipt_hash),
         Bytes::new(),
     ];
 
     // build transaction
-    let tx = TransactionBuilder::default()
+    let mut tx_builder = TransactionBuilder::default()
         .inputs(inputs)
         .outputs(outputs)
         .outputs_data(outputs_data.pack())
         .cell_dep(audit_delay_dep)
         .cell_dep(bridge_dep)
         .cell_dep(always_success_dep)
-        .witnesses(witnesses.pack())
-        .build();
-    let tx = context.complete_tx(tx);
+        .cell_deps(config_deps)
+        .witnesses(witnesses.pack());
+
+    // header deps are only needed to prove a receipt has not expired
+    if let Some((created_at, proof_at)) = params.header_timestamps {
+        let h1 = Header::new_builder()
+            .raw(RawHeader::new_builder().number(1u64.pack()).timestamp(created_at.pack()).build())
+            .build()
+            .into_view();
+        let h2 = Header::new_builder()
+            .raw(RawHeader::new_builder().number(2u64.pack()).timestamp(proof_at.pack()).build())
+            .build()
+            .into_view();
+
+        context.insert_header(h1.clone());
+        context.insert_header(h2.clone());
+        context.link_cell_with_block(prev_bridge_outpoint.clone(), h1.hash(), 0);
+
+        tx_builder = tx_builder.header_dep(h1.hash()).header_dep(h2.hash());
+    }
+
+    let tx = context.complete_tx(tx_builder.build());
 
     // run
     match params.error {
@@ -221,358 +710,765 @@ fn test_payout(params: PayoutTestParams) {
 
 #[test]
 fn test_unlock() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+    test_payout(PayoutTestParams::default());
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_unknown_receipt_signer() {
+    let (_, other_pub_key) = get_val_keys();
+    test_payout(PayoutTestParams {
+        bridge_validators: vec![eth_address(&other_pub_key)],
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
+#[test]
+fn test_unlock_ckb_identity() {
     let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
+    let params = PayoutTestParams::default();
+    test_payout(PayoutTestParams {
+        sig: sign_receipt_ckb(params.receipt, priv_key),
+        bridge_validators: vec![blake160_identity(&pub_key)],
+        ckb_identity: true,
+        ..params
+    });
+}
+
+#[test]
+fn test_ckb_identity_with_ethereum_signature() {
+    // the signature is valid, but for the Ethereum signing scheme
+    let (priv_key, pub_key) = get_val_keys();
+    let params = PayoutTestParams::default();
+    test_payout(PayoutTestParams {
+        sig: sign_receipt(params.receipt, priv_key),
+        bridge_validators: vec![blake160_identity(&pub_key)],
+        ckb_identity: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..params
+    });
+}
+
+#[test]
+fn test_signature_quorum_not_met() {
+    let (_, pub_key1) = get_val_keys();
+    let (_, pub_key2) = get_val_keys();
+    let params = PayoutTestParams::default();
+    let validator_list = vec![params.bridge_validators[0].clone(), eth_address(&pub_key1), eth_address(&pub_key2)];
+    test_payout(PayoutTestParams {
         bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
-        error: None,
-    };
+        error: Some(ScriptError::ValidationFailure(22).input_type_script(0)),
+        ..params
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_invalid_withdrawal_capacity() {
+    test_payout(PayoutTestParams {
+        bridge_after_capacity: 95,
+        error: Some(ScriptError::ValidationFailure(23).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_unknown_receipt_signer() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_data_updated_incorrectly() {
+    test_payout(PayoutTestParams {
+        bridge_data_after: Bytes::new(),
+        error: Some(ScriptError::ValidationFailure(24).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_wrong_trustee_in_payout() {
+    test_payout(PayoutTestParams {
+        bridge_trustee: rand::thread_rng().gen::<[u8; 32]>(),
+        error: Some(ScriptError::ValidationFailure(25).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let (dummy_priv, dummy_pub) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&dummy_pub.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
+#[test]
+fn test_wrong_payout_destination() {
+    test_payout(PayoutTestParams {
+        audit_delay_owner_lock_hash: rand::thread_rng().gen::<[u8; 32]>(),
+        error: Some(ScriptError::ValidationFailure(26).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_wrong_timeout() {
+    test_payout(PayoutTestParams {
+        timeout: 101,
+        error: Some(ScriptError::ValidationFailure(27).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_receipt_already_used() {
+    test_payout(PayoutTestParams {
+        bridge_data_before: bridge_data(0, 0, 0, checkpoint(1, &[0])),
+        error: Some(ScriptError::ValidationFailure(28).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_wrong_bridge_in_payout() {
+    test_payout(PayoutTestParams {
+        audit_delay_bridge_hash: Some(rand::thread_rng().gen::<[u8; 32]>()),
+        error: Some(ScriptError::ValidationFailure(31).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_wrong_payout_data() {
+    test_payout(PayoutTestParams {
+        payout_data: Some(Bytes::new()),
+        error: Some(ScriptError::ValidationFailure(32).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_wrong_audit_delay_hash_type() {
+    test_payout(PayoutTestParams {
+        audit_delay_hash_type: Some(ScriptHashType::Type.into()),
+        error: Some(ScriptError::ValidationFailure(7).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_audit_delay_hash_type_data1() {
+    test_payout(PayoutTestParams {
+        audit_delay_hash_type: Some(Byte::new(HASH_TYPE_DATA1)),
+        error: Some(ScriptError::ValidationFailure(7).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_with_config_cell() {
+    test_payout(PayoutTestParams {
+        config: ConfigCell::Helpers,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_config_unknown_audit_delay() {
+    test_payout(PayoutTestParams {
+        config: ConfigCell::UnknownAuditDelay,
+        error: Some(ScriptError::ValidationFailure(7).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_config_cell_not_found() {
+    test_payout(PayoutTestParams {
+        config: ConfigCell::NotInCellDeps,
+        error: Some(ScriptError::ValidationFailure(36).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_invalid_config_data() {
+    test_payout(PayoutTestParams {
+        config: ConfigCell::InvalidData,
+        error: Some(ScriptError::ValidationFailure(37).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_config_not_type_id() {
+    test_payout(PayoutTestParams {
+        config: ConfigCell::NotTypeId,
+        error: Some(ScriptError::ValidationFailure(60).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_unlock_merkle_validators() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 1, signer_in_set: true, repeat_approval: false, signer_weight: None, claimed_weight: None }),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_merkle_signer_not_in_set() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 1, signer_in_set: false, repeat_approval: false, signer_weight: None, claimed_weight: None }),
         error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
-    };
+        ..Default::default()
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_merkle_threshold_not_met() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 2, signer_in_set: true, repeat_approval: false, signer_weight: None, claimed_weight: None }),
+        error: Some(ScriptError::ValidationFailure(22).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_signature_quorum_not_met() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_merkle_repeated_approval_counts_once() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 2, signer_in_set: true, repeat_approval: true, signer_weight: None, claimed_weight: None }),
+        error: Some(ScriptError::ValidationFailure(22).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_unlock_weighted_validators() {
+    test_payout(PayoutTestParams {
+        weights: Some(WeightParams { threshold: 60, signer_weight: 60, other_weight: 40 }),
+        ..Default::default()
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let (priv_key1, pub_key1) = get_val_keys();
-    let (priv_key2, pub_key2) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_address1 =
-        &Keccak256::digest(&pub_key1.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_address2 =
-        &Keccak256::digest(&pub_key2.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address, validator_address1, validator_address2];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
+#[test]
+fn test_weighted_quorum_not_met() {
+    test_payout(PayoutTestParams {
+        weights: Some(WeightParams { threshold: 60, signer_weight: 59, other_weight: 41 }),
         error: Some(ScriptError::ValidationFailure(22).input_type_script(0)),
-    };
+        ..Default::default()
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_unlock_weighted_merkle_validators() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 5, signer_in_set: true, repeat_approval: false, signer_weight: Some(5), claimed_weight: None }),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_invalid_withdrawal_capacity() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_weighted_merkle_quorum_not_met() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 5, signer_in_set: true, repeat_approval: false, signer_weight: Some(4), claimed_weight: None }),
+        error: Some(ScriptError::ValidationFailure(22).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_weighted_merkle_wrong_claimed_weight() {
+    test_payout(PayoutTestParams {
+        merkle: Some(MerkleParams { threshold: 5, signer_in_set: true, repeat_approval: false, signer_weight: Some(4), claimed_weight: Some(5) }),
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 95,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
-        error: Some(ScriptError::ValidationFailure(23).input_type_script(0)),
-    };
+#[test]
+fn test_unlock_aggregate_signature() {
+    test_payout(PayoutTestParams {
+        schnorr: Some(SchnorrParams { signed_by_aggregate_key: true, truncated: false }),
+        ..Default::default()
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_aggregate_signature_by_other_key() {
+    test_payout(PayoutTestParams {
+        schnorr: Some(SchnorrParams { signed_by_aggregate_key: false, truncated: false }),
+        error: Some(ScriptError::ValidationFailure(44).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_data_updated_incorrectly() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_aggregate_signature_wrong_length() {
+    test_payout(PayoutTestParams {
+        schnorr: Some(SchnorrParams { signed_by_aggregate_key: true, truncated: true }),
+        error: Some(ScriptError::ValidationFailure(14).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_unlock_ethereum_v() {
+    test_payout(PayoutTestParams {
+        sig_encoding: SigEncoding::EthereumV,
+        ..Default::default()
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::new(),
-        funding_amount: 10,
-        timeout: 100,
-        error: Some(ScriptError::ValidationFailure(24).input_type_script(0)),
-    };
+#[test]
+fn test_high_s_signature() {
+    test_payout(PayoutTestParams {
+        sig_encoding: SigEncoding::HighS,
+        error: Some(ScriptError::ValidationFailure(45).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_invalid_recovery_id() {
+    test_payout(PayoutTestParams {
+        sig_encoding: SigEncoding::InvalidV,
+        error: Some(ScriptError::ValidationFailure(46).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_wrong_trustee_in_payout() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_withdrawal_typed_data_hash() {
+    let receipt = with_fee(with_expiry(with_nonce(gen_receipt(10, [0x33u8; 32], [0x22u8; 32]), 5), 1000), 2);
+    let receipt = with_asset(receipt, [0x44u8; 32]);
+    let digest = Keccak256::digest(&withdrawal_message(receipt, [0x11u8; 32], 1));
+    assert_eq!(
+        hex::encode(&digest),
+        "4d5261a0c2f76d5fee93e2cbc463e18167e2cbb98e6536a33e374ef015ece92d"
+    );
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_unlock_eip712() {
+    let (priv_key, pub_key) = get_val_keys();
+    let params = PayoutTestParams::default();
+    test_payout(PayoutTestParams {
+        sig: sign_withdrawal(params.receipt, params.bridge_state_id, CHAIN_ID, priv_key),
+        bridge_validators: vec![eth_address(&pub_key)],
+        eip712_chain_id: Some(CHAIN_ID),
+        ..params
+    });
+}
+
+#[test]
+fn test_eip712_signed_as_message() {
+    test_payout(PayoutTestParams {
+        eip712_chain_id: Some(CHAIN_ID),
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
 
+#[test]
+fn test_eip712_wrong_chain_id() {
     let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: receipt_owner_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
-        error: Some(ScriptError::ValidationFailure(25).input_type_script(0)),
-    };
+    let params = PayoutTestParams::default();
+    test_payout(PayoutTestParams {
+        sig: sign_withdrawal(params.receipt, params.bridge_state_id, CHAIN_ID + 1, priv_key),
+        bridge_validators: vec![eth_address(&pub_key)],
+        eip712_chain_id: Some(CHAIN_ID),
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..params
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_payout_leaves_bridge_under_capacity() {
+    test_payout(PayoutTestParams {
+        reserve_capacity: false,
+        error: Some(ScriptError::ValidationFailure(47).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_wrong_payout_destination() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_payout_slides_nonce_window() {
+    test_payout(PayoutTestParams {
+        bridge_data_before: bridge_data(0, 0, 0, checkpoint(2, &[0, 1])),
+        bridge_data_after: bridge_data(0, PAYOUT_AMOUNT, 1, checkpoint(300, &[299])),
+        ..PayoutTestParams::signed(with_nonce(payout_receipt(), 299))
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_payout_nonce_in_window() {
+    test_payout(PayoutTestParams {
+        bridge_data_before: bridge_data(0, 0, 0, checkpoint(300, &[299])),
+        bridge_data_after: bridge_data(0, PAYOUT_AMOUNT, 1, checkpoint(300, &[100, 299])),
+        ..PayoutTestParams::signed(with_nonce(payout_receipt(), 100))
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, trustee_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 100,
-        error: Some(ScriptError::ValidationFailure(26).input_type_script(0)),
-    };
+#[test]
+fn test_payout_nonce_below_window() {
+    test_payout(PayoutTestParams {
+        bridge_data_before: bridge_data(0, 0, 0, checkpoint(300, &[299])),
+        bridge_data_after: bridge_data(0, PAYOUT_AMOUNT, 1, checkpoint(300, &[299])),
+        error: Some(ScriptError::ValidationFailure(48).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_nonce_out_of_range() {
+    // the high-water mark can't move past the last nonce
+    test_payout(PayoutTestParams {
+        bridge_data_before: bridge_data(0, 0, 0, checkpoint(300, &[299])),
+        error: Some(ScriptError::ValidationFailure(61).input_type_script(0)),
+        ..PayoutTestParams::signed(with_nonce(payout_receipt(), u64::MAX))
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_payout_invalid_bridge_data() {
+    test_payout(PayoutTestParams {
+        bridge_data_before: Bytes::from(vec![0u8; 32]),
+        error: Some(ScriptError::ValidationFailure(49).input_type_script(0)),
+        ..Default::default()
+    });
 }
 
 #[test]
-fn test_wrong_timeout() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_payout_before_expiry() {
+    test_payout(PayoutTestParams {
+        header_timestamps: Some((500, 600)),
+        ..PayoutTestParams::signed(with_expiry(payout_receipt(), 1000))
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_payout_expired_receipt() {
+    test_payout(PayoutTestParams {
+        header_timestamps: Some((500, 1100)),
+        error: Some(ScriptError::ValidationFailure(50).input_type_script(0)),
+        ..PayoutTestParams::signed(with_expiry(payout_receipt(), 1000))
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::default(),
-        bridge_data_after: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        funding_amount: 10,
-        timeout: 101,
-        error: Some(ScriptError::ValidationFailure(27).input_type_script(0)),
-    };
+#[test]
+fn test_payout_bridge_moved_after_expiry() {
+    test_payout(PayoutTestParams {
+        header_timestamps: Some((1100, 900)),
+        error: Some(ScriptError::ValidationFailure(50).input_type_script(0)),
+        ..PayoutTestParams::signed(with_expiry(payout_receipt(), 1000))
+    });
+}
 
-    test_payout(params);
+#[test]
+fn test_payout_expiry_not_proven() {
+    test_payout(PayoutTestParams {
+        error: Some(ScriptError::ValidationFailure(51).input_type_script(0)),
+        ..PayoutTestParams::signed(with_expiry(payout_receipt(), 1000))
+    });
 }
 
 #[test]
-fn test_receipt_already_used() {
-    let trustee_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let bridge_state_id = [0u8; 36];
+fn test_payout_with_relayer_fee() {
+    test_payout(PayoutTestParams {
+        audit_delay_payout_amount: PAYOUT_AMOUNT - 3,
+        change_capacity: 11,
+        ..PayoutTestParams::signed(with_fee(payout_receipt(), 3))
+    });
+}
 
-    let payout_amount = 10;
+#[test]
+fn test_payout_fee_not_deducted() {
+    test_payout(PayoutTestParams {
+        error: Some(ScriptError::ValidationFailure(15).input_type_script(0)),
+        ..PayoutTestParams::signed(with_fee(payout_receipt(), 3))
+    });
+}
 
-    let (priv_key, pub_key) = get_val_keys();
-    let receipt_owner_lock_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt_tx_hash = rand::thread_rng().gen::<[u8; 32]>();
-    let receipt = gen_receipt(payout_amount, receipt_owner_lock_hash, receipt_tx_hash);
-    let sig: recoverable::Signature = sign_receipt(receipt, priv_key);
-
-    let validator_address =
-        &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
-    let validator_list = vec![validator_address];
-
-    let params = PayoutTestParams {
-        audit_delay_trustee_lock_hash: trustee_lock_hash,
-        audit_delay_owner_lock_hash: receipt_owner_lock_hash,
-        audit_delay_payout_amount: payout_amount,
-        bridge_state_id: bridge_state_id,
-        bridge_validators: validator_list,
-        bridge_trustee: trustee_lock_hash,
-        sig: sig,
-        receipt: receipt,
-        bridge_after_capacity: 90,
-        bridge_before_capacity: 100,
-        change_capacity: 8,
-        bridge_data_before: Bytes::from(Vec::from(&Keccak256::digest(&receipt[..])[..])),
-        bridge_data_after: Bytes::from([&Keccak256::digest(&receipt[..])[..], &Keccak256::digest(&receipt[..])[..]].concat()),
-        funding_amount: 10,
-        timeout: 100,
-        error: Some(ScriptError::ValidationFailure(28).input_type_script(0)),
+#[test]
+fn test_payout_fee_exceeds_amount() {
+    test_payout(PayoutTestParams {
+        audit_delay_payout_amount: 0,
+        change_capacity: 18,
+        error: Some(ScriptError::ValidationFailure(52).input_type_script(0)),
+        ..PayoutTestParams::signed(with_fee(payout_receipt(), 11))
+    });
+}
+
+#[test]
+fn test_payout_wrong_withdrawn_total() {
+    test_payout(PayoutTestParams {
+        bridge_data_after: bridge_data(0, PAYOUT_AMOUNT - 1, 1, checkpoint(1, &[0])),
+        error: Some(ScriptError::ValidationFailure(24).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+struct ReturnVetoTestParams {
+    audit_delay_trustee_is_bridge_trustee: bool,
+    // the bridge trustee unlocks an input of its own when it isn't the payout trustee
+    signed_by_trustee: bool,
+    vetoed_nonce: u64,
+    // the witness carries the receipt of the vetoed payout
+    receipt_in_witness: bool,
+    vetoed_amount: u64,
+    bridge_before_capacity: u64,
+    bridge_after_capacity: u64,
+    bridge_data_before: Bytes,
+    bridge_data_after: Bytes,
+    error: Option<TransactionScriptError>,
+}
+
+// the trustee returns a payout of 10 with nonce 0
+impl Default for ReturnVetoTestParams {
+    fn default() -> Self {
+        ReturnVetoTestParams {
+            audit_delay_trustee_is_bridge_trustee: true,
+            signed_by_trustee: true,
+            vetoed_nonce: 0,
+            receipt_in_witness: true,
+            vetoed_amount: 10,
+            bridge_before_capacity: 90,
+            bridge_after_capacity: 100,
+            bridge_data_before: bridge_data(0, 10, 1, checkpoint(1, &[0])),
+            bridge_data_after: bridge_data(0, 0, 1, checkpoint(1, &[])),
+            error: None,
+        }
+    }
+}
+
+fn test_return_vetoed_payout(params: ReturnVetoTestParams) {
+    let mut context = Context::default();
+
+    // load binaries
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let audit_delay_bin: Bytes = Loader::default().load_binary("audit-delay");
+
+    // deploy binaries to cells
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let audit_delay_out_point = context.deploy_cell(audit_delay_bin);
+
+    // make dep objects for our contracts
+    let always_success_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let bridge_dep = CellDep::new_builder()
+        .out_point(bridge_out_point.clone())
+        .build();
+    let audit_delay_dep = CellDep::new_builder()
+        .out_point(audit_delay_out_point.clone())
+        .build();
+
+    // make our script objects
+    let always_success_script = context
+        .build_script(&always_success_out_point, Bytes::default())
+        .expect("script");
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+    let audit_delay_trustee_script = if params.audit_delay_trustee_is_bridge_trustee {
+        trustee_script.clone()
+    } else {
+        context
+            .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+            .expect("script")
     };
+    let bridge_args = Bytes::from(
+        [
+            &[0u8; 32][..],
+            &*trustee_script.calc_script_hash().raw_data(),
+            &MIN_DEPOSIT.to_be_bytes()[..],
+            &NO_CONFIG[..],
+            &[VALIDATOR_MODE_LIST][..],
+            &rand::thread_rng().gen::<[u8; 20]>()[..],
+        ]
+        .concat(),
+    );
+    let bridge_script = context
+        .build_script(&bridge_out_point, bridge_args)
+        .expect("script");
+    let audit_delay_args = Bytes::from(
+        [
+            &*audit_delay_trustee_script.calc_script_hash().raw_data(),
+            &rand::thread_rng().gen::<[u8; 32]>()[..],
+            &100u64.to_be_bytes()[..],
+            &*bridge_script.calc_script_hash().raw_data(),
+        ]
+        .concat(),
+    );
+    let audit_delay_script = context
+        .build_script(&audit_delay_out_point, audit_delay_args)
+        .expect("script");
+
+    // bridge witness
+    let receipt = with_nonce(
+        gen_receipt(params.vetoed_amount, rand::thread_rng().gen::<[u8; 32]>(), rand::thread_rng().gen::<[u8; 32]>()),
+        params.vetoed_nonce,
+    );
+    let vetoed_receipt_hash = Bytes::from(Keccak256::digest(&receipt[..]).to_vec());
+    let witness_receipt = if params.receipt_in_witness { receipt.to_vec() } else { vec![0u8; 128] };
+    let bridge_witness = Bytes::from([&[3u8][..], &witness_receipt[..]].concat());
+
+    // input outpoints
+    let prev_bridge_output = CellOutput::new_builder()
+        .capacity(params.bridge_before_capacity.pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build();
+    let trustee_output = CellOutput::new_builder()
+        .capacity(10u64.pack())
+        .lock(audit_delay_trustee_script.clone())
+        .build();
+    let payout_output = CellOutput::new_builder()
+        .capacity(params.vetoed_amount.pack())
+        .lock(audit_delay_script.clone())
+        .build();
+
+    let prev_bridge_outpoint = context.create_cell(prev_bridge_output, params.bridge_data_before);
+    let trustee_outpoint = context.create_cell(trustee_output, Bytes::default());
+    let payout_outpoint = context.create_cell(payout_output, vetoed_receipt_hash);
+
+    let mut inputs = vec![
+        // bridge input
+        CellInput::new_builder()
+            .previous_output(prev_bridge_outpoint)
+            .build(),
+        // trustee input
+        CellInput::new_builder()
+            .previous_output(trustee_outpoint)
+            .build(),
+        // vetoed payout input
+        CellInput::new_builder()
+            .previous_output(payout_outpoint)
+            .build(),
+    ];
+    if params.signed_by_trustee && !params.audit_delay_trustee_is_bridge_trustee {
+        // bridge trustee input
+        let bridge_trustee_output = CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(trustee_script.clone())
+            .build();
+        let bridge_trustee_outpoint = context.create_cell(bridge_trustee_output, Bytes::default());
+        inputs.push(
+            CellInput::new_builder()
+                .previous_output(bridge_trustee_outpoint)
+                .build(),
+        );
+    }
+
+    let outputs = vec![
+        // bridge output
+        CellOutput::new_builder()
+            .capacity(params.bridge_after_capacity.pack())
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        // change output
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(audit_delay_trustee_script.clone())
+            .build(),
+    ];
+
+    let mut witnesses = vec![Bytes::new(); inputs.len()];
+    witnesses[0] = bridge_witness;
+
+    let outputs_data = vec![params.bridge_data_after, Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(audit_delay_dep)
+        .cell_dep(bridge_dep)
+        .cell_dep(always_success_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
 
-    test_payout(params);
+    // run
+    match params.error {
+        None => {
+            context
+                .verify_tx(&tx, MAX_CYCLES)
+                .expect("pass verification");
+        }
+        Some(error) => {
+            let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+            assert_error_eq!(err, error);
+        }
+    }
+}
+
+#[test]
+fn test_return_vetoed_payout_success() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        vetoed_nonce: 1,
+        bridge_data_before: bridge_data(0, 10, 1, checkpoint(2, &[0, 1])),
+        bridge_data_after: bridge_data(0, 0, 1, checkpoint(2, &[0])),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_below_nonce_window() {
+    // the nonce stays used, only the capacity goes back
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        bridge_data_before: bridge_data(0, 10, 1, checkpoint(300, &[299])),
+        bridge_data_after: bridge_data(0, 0, 1, checkpoint(300, &[299])),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_wrong_trustee() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        audit_delay_trustee_is_bridge_trustee: false,
+        error: Some(ScriptError::ValidationFailure(25).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_not_signed_by_trustee() {
+    // whoever else unlocks the payout can't return it to collect the fee again
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        audit_delay_trustee_is_bridge_trustee: false,
+        signed_by_trustee: false,
+        error: Some(ScriptError::ValidationFailure(18).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_capacity_computed_incorrectly() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        bridge_after_capacity: 105,
+        error: Some(ScriptError::ValidationFailure(34).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_unknown_vetoed_receipt() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        bridge_data_before: Bytes::new(),
+        bridge_data_after: Bytes::new(),
+        error: Some(ScriptError::ValidationFailure(35).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_other_receipt_in_witness() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        receipt_in_witness: false,
+        error: Some(ScriptError::ValidationFailure(35).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_return_vetoed_payout_data_updated_incorrectly() {
+    test_return_vetoed_payout(ReturnVetoTestParams {
+        bridge_data_after: bridge_data(0, 10, 1, checkpoint(1, &[0])),
+        error: Some(ScriptError::ValidationFailure(24).input_type_script(0)),
+        ..Default::default()
+    });
 }
@@ -0,0 +1,272 @@
+use super::*;
+use crate::payout::get_val_keys;
+use ckb_testtool::context::Context;
+use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::ckb_script::{TransactionScriptError, ScriptError};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::Rng;
+use sha3::{Digest, Keccak256};
+
+const SHARDED_FLAG: u8 = 0x20;
+const SHARD_COUNT: u8 = 2;
+
+enum Action {
+    // create all shards of a new bridge
+    Deploy,
+    // move capacity between the shards
+    Rebalance,
+    // pay out a receipt from the first shard
+    Payout,
+}
+
+struct ShardTestParams {
+    action: Action,
+    // shard index of every bridge cell, in input and output order
+    shards: Vec<u8>,
+    // capacities of the shards on top of what they need themselves
+    capacities_before: Vec<u64>,
+    capacities_after: Vec<u64>,
+    // the last shard's counters change in a rebalance
+    change_data: bool,
+    // a key outside the validator set signs
+    signed_by_stranger: bool,
+    // receipt nonce and amount of a payout
+    nonce: u64,
+    amount: u64,
+    error: Option<TransactionScriptError>,
+}
+
+impl Default for ShardTestParams {
+    fn default() -> Self {
+        ShardTestParams {
+            action: Action::Rebalance,
+            shards: vec![0, 1],
+            capacities_before: vec![1000, 0],
+            capacities_after: vec![400, 600],
+            change_data: false,
+            signed_by_stranger: false,
+            nonce: 1,
+            amount: 10,
+            error: None,
+        }
+    }
+}
+
+fn shard_data(shard: u8, bridge_data: Bytes) -> Bytes {
+    Bytes::from([&[shard][..], &bridge_data[..]].concat())
+}
+
+fn test_shards(params: ShardTestParams) {
+    let mut context = Context::default();
+    let fixture = BridgeFixture::deploy(&mut context);
+
+    // make our script objects
+    let (priv_key, pub_key) = get_val_keys();
+    let (stranger_key, _) = get_val_keys();
+    let validator_address = &Keccak256::digest(&pub_key.to_encoded_point(false).as_bytes()[1..65])[12..];
+    let signing_key = if params.signed_by_stranger { &stranger_key } else { &priv_key };
+    let always_success_script = fixture.always_success_script.clone();
+    let trustee_hash = rand::thread_rng().gen::<[u8; 32]>();
+    let funding_outpoint = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .build(),
+        Bytes::default(),
+    );
+    let funding_input = CellInput::new_builder()
+        .previous_output(funding_outpoint)
+        .build();
+    // the shard count follows the mode byte
+    let bridge_script = fixture.bridge_script(
+        &mut context,
+        &type_id(&funding_input, 0),
+        &trustee_hash,
+        &[&[VALIDATOR_MODE_LIST | SHARDED_FLAG, SHARD_COUNT][..], validator_address].concat(),
+    );
+    let bridge_hash = bridge_script.calc_script_hash().raw_data();
+    let bridge_guard_script = fixture.bridge_guard_script(&mut context, &bridge_hash, &trustee_hash);
+    let shard_output = CellOutput::new_builder()
+        .lock(bridge_guard_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build();
+    let empty_data = bridge_data(0, 0, 0, checkpoint(0, &[]));
+    let floor = bridge_capacity_floor(&shard_output, 1 + empty_data.len());
+    let shard_cell = |capacity: u64| shard_output.clone().as_builder().capacity((floor + capacity).pack()).build();
+
+    let mut inputs = vec![funding_input];
+    let mut outputs = Vec::new();
+    let mut outputs_data = Vec::new();
+    let bridge_witness = match params.action {
+        Action::Deploy => {
+            for shard in params.shards.iter() {
+                outputs.push(shard_cell(0));
+                outputs_data.push(Bytes::from(vec![*shard]));
+            }
+            Bytes::new()
+        }
+        Action::Rebalance => {
+            let mut message = bridge_hash.to_vec();
+            inputs.clear();
+            for (shard, capacity) in params.shards.iter().zip(params.capacities_before.iter()) {
+                let outpoint = context.create_cell(shard_cell(*capacity), shard_data(*shard, empty_data.clone()));
+                message.extend_from_slice(outpoint.as_slice());
+                inputs.push(CellInput::new_builder().previous_output(outpoint).build());
+            }
+            for (shard, capacity) in params.shards.iter().zip(params.capacities_after.iter()) {
+                outputs.push(shard_cell(*capacity));
+                outputs_data.push(shard_data(*shard, empty_data.clone()));
+                message.push(*shard);
+                message.extend_from_slice(&(floor + capacity).to_be_bytes());
+            }
+            if params.change_data {
+                let last = outputs_data.len() - 1;
+                outputs_data[last] = shard_data(params.shards[last], bridge_data(1, 0, 0, checkpoint(0, &[])));
+            }
+            Bytes::from([&[5u8][..], &personal_sign(&message, signing_key)[..]].concat())
+        }
+        Action::Payout => {
+            let shard = params.shards[0];
+            let capacity_before = params.capacities_before[0];
+            let outpoint = context.create_cell(shard_cell(capacity_before), shard_data(shard, empty_data.clone()));
+            inputs.insert(0, CellInput::new_builder().previous_output(outpoint).build());
+
+            // every shard marks its own nonces
+            let local_nonce = params.nonce / SHARD_COUNT as u64;
+            outputs.push(shard_cell(capacity_before - params.amount));
+            outputs_data.push(shard_data(
+                shard,
+                bridge_data(0, params.amount, 1, checkpoint(local_nonce + 1, &[local_nonce])),
+            ));
+
+            let owner_hash = rand::thread_rng().gen::<[u8; 32]>();
+            let mut receipt = [0u8; 128];
+            receipt[32..40].copy_from_slice(&params.nonce.to_be_bytes());
+            receipt[56..64].copy_from_slice(&params.amount.to_be_bytes());
+            receipt[64..96].copy_from_slice(&owner_hash);
+            receipt[96..128].copy_from_slice(&rand::thread_rng().gen::<[u8; 32]>());
+            let audit_delay_script = context
+                .build_script(
+                    &fixture.audit_delay_out_point,
+                    Bytes::from([&trustee_hash[..], &owner_hash[..], &TIMEOUT.to_be_bytes()[..], &*bridge_hash].concat()),
+                )
+                .expect("script");
+            outputs.push(
+                CellOutput::new_builder()
+                    .capacity(params.amount.pack())
+                    .lock(audit_delay_script)
+                    .build(),
+            );
+            outputs_data.push(Bytes::from(Keccak256::digest(&receipt[..]).to_vec()));
+            Bytes::from([&[0u8][..], &receipt[..], &personal_sign(&receipt, signing_key)[..]].concat())
+        }
+    };
+
+    let mut witnesses = vec![Bytes::new(); inputs.len()];
+    witnesses[0] = bridge_witness;
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .inputs(inputs)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_deps(fixture.cell_deps)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    check_tx(&context, &tx, params.error);
+}
+
+#[test]
+fn test_deploy_shards() {
+    test_shards(ShardTestParams {
+        action: Action::Deploy,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_deploy_shards_out_of_order() {
+    test_shards(ShardTestParams {
+        action: Action::Deploy,
+        shards: vec![1, 0],
+        error: Some(ScriptError::ValidationFailure(57).output_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_deploy_missing_shard() {
+    test_shards(ShardTestParams {
+        action: Action::Deploy,
+        shards: vec![0],
+        error: Some(ScriptError::ValidationFailure(57).output_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_rebalance() {
+    test_shards(Default::default());
+}
+
+#[test]
+fn test_rebalance_changes_total_capacity() {
+    test_shards(ShardTestParams {
+        capacities_after: vec![400, 500],
+        error: Some(ScriptError::ValidationFailure(59).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_rebalance_changes_data() {
+    test_shards(ShardTestParams {
+        change_data: true,
+        error: Some(ScriptError::ValidationFailure(24).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_rebalance_not_signed_by_validators() {
+    test_shards(ShardTestParams {
+        signed_by_stranger: true,
+        error: Some(ScriptError::ValidationFailure(21).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_rebalance_single_shard() {
+    test_shards(ShardTestParams {
+        shards: vec![0],
+        capacities_before: vec![1000],
+        capacities_after: vec![1000],
+        error: Some(ScriptError::ValidationFailure(58).input_type_script(0)),
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_from_shard() {
+    test_shards(ShardTestParams {
+        action: Action::Payout,
+        shards: vec![1],
+        nonce: 3,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn test_payout_from_wrong_shard() {
+    test_shards(ShardTestParams {
+        action: Action::Payout,
+        shards: vec![0],
+        nonce: 3,
+        error: Some(ScriptError::ValidationFailure(57).input_type_script(0)),
+        ..Default::default()
+    });
+}
@@ -1,11 +1,9 @@
 use super::*;
 use ckb_testtool::{context::Context};
-use ckb_tool::ckb_types::{bytes::Bytes, core::TransactionBuilder, packed::*, prelude::*};
+use ckb_tool::ckb_types::{bytes::Bytes, core::{TransactionBuilder, TransactionView}, packed::*, prelude::*};
 use ckb_tool::{ckb_error::assert_error_eq, ckb_script::ScriptError};
 use hex::FromHex;
 
-const MAX_CYCLES: u64 = 100_000_000;
-
 #[test]
 fn test_deploy() {
     let mut context = Context::default();
@@ -32,11 +30,9 @@ fn test_deploy() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -44,10 +40,16 @@ fn test_deploy() {
         .build_script(&contract_out_point, type_script_args)
         .expect("script");
     let bridge_script_dep = CellDep::new_builder().out_point(contract_out_point).build();
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_args = Bytes::from([&*bridge_script.calc_script_hash().raw_data(), &*trustee].concat());
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
 
     let outputs = vec![CellOutput::new_builder()
         .capacity(0u64.pack())
-        .lock(lock_script.clone())
+        .lock(bridge_guard_script.clone())
         .type_(Some(bridge_script.clone()).pack())
         .build()];
 
@@ -105,7 +107,7 @@ fn test_deploy() {
 //     let state_id = Bytes::from([tx_hash, index].concat());
 
 //     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    // let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    // let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
 //     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
 //     let contract_out_point = context.deploy_cell(contract_bin);
@@ -167,12 +169,10 @@ fn test_wrong_validator_list_length() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -231,12 +231,10 @@ fn test_wrong_lock_script() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -296,12 +294,10 @@ fn test_wrong_type_script() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -309,11 +305,17 @@ fn test_wrong_type_script() {
         .build_script(&contract_out_point, type_script_args)
         .expect("script");
     let bridge_script_dep = CellDep::new_builder().out_point(contract_out_point).build();
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_args = Bytes::from([&*bridge_script.calc_script_hash().raw_data(), &*trustee].concat());
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
 
     let outputs = vec![
         CellOutput::new_builder()
             .capacity(0u64.pack())
-            .lock(lock_script.clone())
+            .lock(bridge_guard_script.clone())
             .type_(Some(lock_script.clone()).pack())
             .build(),
         CellOutput::new_builder()
@@ -367,12 +369,10 @@ fn test_data_length_not_zero() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -380,10 +380,16 @@ fn test_data_length_not_zero() {
         .build_script(&contract_out_point, type_script_args)
         .expect("script");
     let bridge_script_dep = CellDep::new_builder().out_point(contract_out_point).build();
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_args = Bytes::from([&*bridge_script.calc_script_hash().raw_data(), &*trustee].concat());
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
 
     let outputs = vec![CellOutput::new_builder()
         .capacity(0u64.pack())
-        .lock(lock_script.clone())
+        .lock(bridge_guard_script.clone())
         .type_(Some(bridge_script.clone()).pack())
         .build()];
 
@@ -432,13 +438,11 @@ fn test_wrong_state_id() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    // let index: &[u8] = &*input.previous_output().index().raw_data();
-    let scrambler: &[u8] = &[1, 1, 1, 1];
-    let state_id = Bytes::from([tx_hash, scrambler].concat());
+    // the bridge is output 0, not output 1
+    let state_id = Bytes::from(type_id(&input, 1).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -446,10 +450,16 @@ fn test_wrong_state_id() {
         .build_script(&contract_out_point, type_script_args)
         .expect("script");
     let bridge_script_dep = CellDep::new_builder().out_point(contract_out_point).build();
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_args = Bytes::from([&*bridge_script.calc_script_hash().raw_data(), &*trustee].concat());
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
 
     let outputs = vec![CellOutput::new_builder()
         .capacity(0u64.pack())
-        .lock(lock_script.clone())
+        .lock(bridge_guard_script.clone())
         .type_(Some(bridge_script.clone()).pack())
         .build()];
 
@@ -498,12 +508,10 @@ fn test_too_many_type_outputs() {
         .previous_output(input_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&input, 0).to_vec());
 
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let contract_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let contract_out_point = context.deploy_cell(contract_bin);
@@ -544,6 +552,95 @@ fn test_too_many_type_outputs() {
     assert_error_eq!(err, ScriptError::ValidationFailure(11).output_type_script(0));
 }
 
+#[test]
+fn test_too_many_type_inputs() {
+    let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
+
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let always_success_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let always_success_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point)
+        .build();
+
+    let bridge_creation_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let bridge_creation_input = CellInput::new_builder()
+        .previous_output(bridge_creation_out_point)
+        .build();
+
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
+
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let bridge_script = context
+        .build_script(&bridge_out_point, type_script_args)
+        .expect("script");
+    let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    // a second cell with the same bridge type can't exist under Type ID rules
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(10u64.pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+
+    let outputs_data = vec![Bytes::new(); 1];
+
+    let witnesses = vec![Bytes::from(&[1u8][..])];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(always_success_script_dep)
+        .cell_dep(bridge_script_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(38).input_type_script(0));
+}
+
 #[test]
 fn test_deposit_with_lock() {
     let mut context = Context::default();
@@ -781,11 +878,9 @@ fn test_collect_1_deposit() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -834,7 +929,7 @@ fn test_collect_1_deposit() {
         .type_(Some(bridge_script.clone()).pack())
         .build()];
 
-    let outputs_data = vec![Bytes::new(); 1];
+    let outputs_data = vec![bridge_data(10, 0, 0, checkpoint(0, &[]))];
 
     let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new()];
 
@@ -884,11 +979,9 @@ fn test_collect_3_deposits() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -959,7 +1052,7 @@ fn test_collect_3_deposits() {
         .type_(Some(bridge_script.clone()).pack())
         .build()];
 
-    let outputs_data = vec![Bytes::new(); 1];
+    let outputs_data = vec![bridge_data(60, 0, 0, checkpoint(0, &[]))];
 
     let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new()];
 
@@ -1011,11 +1104,9 @@ fn test_collect_desposits_invalid_sum() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1114,7 +1205,7 @@ fn test_collect_desposits_invalid_sum() {
 }
 
 #[test]
-fn test_collect_deposit_fiddling_with_data() {
+fn test_collect_with_decoy_at_index_0() {
     let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
 
     let mut context = Context::default();
@@ -1125,25 +1216,14 @@ fn test_collect_deposit_fiddling_with_data() {
         .build_script(&always_success_out_point, Default::default())
         .expect("script");
     let always_success_script_dep = CellDep::new_builder()
-        .out_point(always_success_out_point)
-        .build();
-
-    let bridge_creation_out_point = context.create_cell(
-        CellOutput::new_builder()
-            .capacity(10u64.pack())
-            .lock(always_success_script.clone())
-            .build(),
-        Default::default(),
-    );
-    let bridge_creation_input = CellInput::new_builder()
-        .previous_output(bridge_creation_out_point)
+        .out_point(always_success_out_point.clone())
         .build();
+    let decoy_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![7u8]))
+        .expect("script");
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&[0u8; 32][..], &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1152,20 +1232,33 @@ fn test_collect_deposit_fiddling_with_data() {
         .expect("script");
     let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
 
-    let input0_out_point = context.create_cell(
+    // a cell without bridge data takes index 0 on both sides
+    let decoy_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
+            .lock(decoy_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(decoy_out_point)
+        .build();
+
+    let bridge_input_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(100u64.pack())
             .lock(always_success_script.clone())
             .type_(Some(bridge_script.clone()).pack())
             .build(),
         Default::default(),
     );
-    let input0 = CellInput::new_builder()
-        .previous_output(input0_out_point)
+    let input1 = CellInput::new_builder()
+        .previous_output(bridge_input_out_point)
         .build();
 
+    // the deposit lock accepts the decoy at input 0
     let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
-    let deposit_lock_args: Bytes = Bytes::from([&[0; 32], &*bridge_script_hash].concat());
+    let deposit_lock_args: Bytes = Bytes::from([&*decoy_script.calc_script_hash().raw_data(), &*bridge_script_hash].concat());
     let deposit_lock_bin: Bytes = Loader::default().load_binary("deposit-lock");
     let deposit_lock_out_point = context.deploy_cell(deposit_lock_bin);
     let deposit_lock_script = context
@@ -1175,31 +1268,39 @@ fn test_collect_deposit_fiddling_with_data() {
         .out_point(deposit_lock_out_point)
         .build();
 
-    let input1_out_point = context.create_cell(
+    let deposit_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
             .lock(deposit_lock_script.clone())
             .build(),
         Default::default(),
     );
-    let input1 = CellInput::new_builder()
-        .previous_output(input1_out_point)
+    let input2 = CellInput::new_builder()
+        .previous_output(deposit_out_point)
         .build();
 
-    let outputs = vec![CellOutput::new_builder()
-        .capacity(20u64.pack())
-        .lock(always_success_script.clone())
-        .type_(Some(bridge_script.clone()).pack())
-        .build()];
+    // the decoy grows by the deposit while the bridge is drained
+    let outputs = vec![
+        CellOutput::new_builder()
+            .capacity(20u64.pack())
+            .lock(decoy_script.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+    ];
 
-    let outputs_data = vec![Bytes::from(&[1u8][..])];
+    let outputs_data = vec![Bytes::new(), bridge_data(10, 0, 0, checkpoint(0, &[]))];
 
-    let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new()];
+    let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new(), Bytes::new()];
 
     // build transaction
     let tx = TransactionBuilder::default()
         .input(input0)
         .input(input1)
+        .input(input2)
         .outputs(outputs)
         .outputs_data(outputs_data.pack())
         .cell_dep(always_success_script_dep)
@@ -1214,28 +1315,28 @@ fn test_collect_deposit_fiddling_with_data() {
         .verify_tx(&tx, MAX_CYCLES)
         .unwrap_err();
 
-    assert_error_eq!(err, ScriptError::ValidationFailure(17).input_type_script(0));
+    assert_error_eq!(err, ScriptError::ValidationFailure(16).input_type_script(1));
 }
 
 #[test]
-fn test_dissolve_bridge_success() {
+fn test_collect_deposit_fiddling_with_data() {
+    let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
+
     let mut context = Context::default();
 
     let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
     let always_success_out_point = context.deploy_cell(always_success_bin);
-    let lock_script = context
+    let always_success_script = context
         .build_script(&always_success_out_point, Default::default())
         .expect("script");
-    let lock_script_dep = CellDep::new_builder()
+    let always_success_script_dep = CellDep::new_builder()
         .out_point(always_success_out_point)
         .build();
 
-    let validator_list = Bytes::from(Vec::from_hex("1122334411223343241123344112233441122344112233441122334411223344000000000000000000000000112233445566778899001122334455667788990000000000000000000000000000000000000000000000000000000000000004D2AAAAAAAA").unwrap());
-    
     let bridge_creation_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
-            .lock(lock_script.clone())
+            .lock(always_success_script.clone())
             .build(),
         Default::default(),
     );
@@ -1243,11 +1344,9 @@ fn test_dissolve_bridge_success() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
-    let trustee = lock_script.calc_script_hash().raw_data();
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1259,44 +1358,46 @@ fn test_dissolve_bridge_success() {
     let input0_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
-            .lock(lock_script.clone())
+            .lock(always_success_script.clone())
             .type_(Some(bridge_script.clone()).pack())
             .build(),
         Default::default(),
     );
-
     let input0 = CellInput::new_builder()
         .previous_output(input0_out_point)
         .build();
-    
-    let input_out_point1 = context.create_cell(
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let deposit_lock_args: Bytes = Bytes::from([&[0; 32], &*bridge_script_hash].concat());
+    let deposit_lock_bin: Bytes = Loader::default().load_binary("deposit-lock");
+    let deposit_lock_out_point = context.deploy_cell(deposit_lock_bin);
+    let deposit_lock_script = context
+        .build_script(&deposit_lock_out_point, deposit_lock_args)
+        .expect("script");
+    let deposit_lock_dep = CellDep::new_builder()
+        .out_point(deposit_lock_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
-            .lock(lock_script.clone())
+            .lock(deposit_lock_script.clone())
             .build(),
         Default::default(),
     );
-
     let input1 = CellInput::new_builder()
-        .previous_output(input_out_point1)
+        .previous_output(input1_out_point)
         .build();
 
-    let outputs = vec![
-        CellOutput::new_builder()
-            .capacity(5u64.pack())
-            .lock(lock_script.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity(5u64.pack())
-            .lock(lock_script.clone())
-            .build()
-    ];
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(20u64.pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
 
-    let outputs_data = vec![Bytes::new(); 2];
+    let outputs_data = vec![Bytes::from(&[1u8][..])];
 
-    // in combat the secp256 lock script would check the withness
-    // for a signature, hence we can't use an actionByte here.
-    let witnesses = vec![Bytes::from(&[2u8][..]), Bytes::new()];
+    let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new()];
 
     // build transaction
     let tx = TransactionBuilder::default()
@@ -1304,37 +1405,40 @@ fn test_dissolve_bridge_success() {
         .input(input1)
         .outputs(outputs)
         .outputs_data(outputs_data.pack())
-        .cell_dep(lock_script_dep)
+        .cell_dep(always_success_script_dep)
+        .cell_dep(deposit_lock_dep)
         .cell_dep(bridge_script_dep)
         .witnesses(witnesses.pack())
         .build();
     let tx = context.complete_tx(tx);
 
     // run
-    let cycles = context
+    let err = context
         .verify_tx(&tx, MAX_CYCLES)
-        .expect("pass verification");
+        .unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(17).input_type_script(0));
 }
 
 #[test]
-fn test_not_signed_by_trustee() {
+fn test_collect_no_deposits() {
+    let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
+
     let mut context = Context::default();
 
     let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
     let always_success_out_point = context.deploy_cell(always_success_bin);
-    let lock_script = context
+    let always_success_script = context
         .build_script(&always_success_out_point, Default::default())
         .expect("script");
-    let lock_script_dep = CellDep::new_builder()
+    let always_success_script_dep = CellDep::new_builder()
         .out_point(always_success_out_point)
         .build();
 
-    let validator_list = Bytes::from(Vec::from_hex("1122334411223343241123344112233441122344112233441122334411223344000000000000000000000000112233445566778899001122334455667788990000000000000000000000000000000000000000000000000000000000000004D2AAAAAAAA").unwrap());
-    
     let bridge_creation_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
-            .lock(lock_script.clone())
+            .lock(always_success_script.clone())
             .build(),
         Default::default(),
     );
@@ -1342,11 +1446,9 @@ fn test_not_signed_by_trustee() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
     let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1358,44 +1460,133 @@ fn test_not_signed_by_trustee() {
     let input0_out_point = context.create_cell(
         CellOutput::new_builder()
             .capacity(10u64.pack())
-            .lock(lock_script.clone())
+            .lock(always_success_script.clone())
             .type_(Some(bridge_script.clone()).pack())
             .build(),
         Default::default(),
     );
-
     let input0 = CellInput::new_builder()
         .previous_output(input0_out_point)
         .build();
-    
-    let input_out_point1 = context.create_cell(
-        CellOutput::new_builder()
-            .capacity(10u64.pack())
-            .lock(lock_script.clone())
-            .build(),
-        Default::default(),
-    );
 
-    let input1 = CellInput::new_builder()
-        .previous_output(input_out_point1)
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let deposit_lock_args: Bytes = Bytes::from([&[0; 32], &*bridge_script_hash].concat());
+    let deposit_lock_bin: Bytes = Loader::default().load_binary("deposit-lock");
+    let deposit_lock_out_point = context.deploy_cell(deposit_lock_bin);
+    let deposit_lock_script = context
+        .build_script(&deposit_lock_out_point, deposit_lock_args)
+        .expect("script");
+    let deposit_lock_dep = CellDep::new_builder()
+        .out_point(deposit_lock_out_point)
         .build();
 
-    let outputs = vec![
-        CellOutput::new_builder()
-            .capacity(5u64.pack())
-            .lock(lock_script.clone())
-            .build(),
-        CellOutput::new_builder()
-            .capacity(5u64.pack())
-            .lock(lock_script.clone())
-            .build()
-    ];
-
-    let outputs_data = vec![Bytes::new(); 2];
-
-    // in combat the secp256 lock script would check the withness
-    // for a signature, hence we can't use an actionByte here.
-    let witnesses = vec![Bytes::from(&[2u8][..]), Bytes::new()];
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(10u64.pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+
+    let outputs_data = vec![Bytes::new(); 1];
+
+    let witnesses = vec![Bytes::from(&[1u8][..])];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(always_success_script_dep)
+        .cell_dep(deposit_lock_dep)
+        .cell_dep(bridge_script_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(29).input_type_script(0));
+}
+
+#[test]
+fn test_collect_deposit_below_minimum() {
+    let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
+
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let always_success_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let always_success_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point)
+        .build();
+
+    let bridge_creation_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let bridge_creation_input = CellInput::new_builder()
+        .previous_output(bridge_creation_out_point)
+        .build();
+
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
+
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let bridge_script = context
+        .build_script(&bridge_out_point, type_script_args)
+        .expect("script");
+    let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let deposit_lock_args: Bytes = Bytes::from([&[0; 32], &*bridge_script_hash].concat());
+    let deposit_lock_bin: Bytes = Loader::default().load_binary("deposit-lock");
+    let deposit_lock_out_point = context.deploy_cell(deposit_lock_bin);
+    let deposit_lock_script = context
+        .build_script(&deposit_lock_out_point, deposit_lock_args)
+        .expect("script");
+    let deposit_lock_dep = CellDep::new_builder()
+        .out_point(deposit_lock_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity((MIN_DEPOSIT - 1).pack())
+            .lock(deposit_lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity((10 + MIN_DEPOSIT - 1).pack())
+        .lock(always_success_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+
+    let outputs_data = vec![Bytes::new(); 1];
+
+    let witnesses = vec![Bytes::from(&[1u8][..]), Bytes::new()];
 
     // build transaction
     let tx = TransactionBuilder::default()
@@ -1403,22 +1594,20 @@ fn test_not_signed_by_trustee() {
         .input(input1)
         .outputs(outputs)
         .outputs_data(outputs_data.pack())
-        .cell_dep(lock_script_dep)
+        .cell_dep(always_success_script_dep)
+        .cell_dep(deposit_lock_dep)
         .cell_dep(bridge_script_dep)
         .witnesses(witnesses.pack())
         .build();
     let tx = context.complete_tx(tx);
 
-    // run
-    let err = context
-        .verify_tx(&tx, MAX_CYCLES)
-        .unwrap_err();
+    let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
 
-    assert_error_eq!(err, ScriptError::ValidationFailure(18).input_type_script(0));
+    assert_error_eq!(err, ScriptError::ValidationFailure(30).input_type_script(0));
 }
 
 #[test]
-fn test_bridge_was_not_dissolved() {
+fn test_dissolve_bridge_success() {
     let mut context = Context::default();
 
     let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
@@ -1443,11 +1632,9 @@ fn test_bridge_was_not_dissolved() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
     let trustee = lock_script.calc_script_hash().raw_data();
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1481,13 +1668,18 @@ fn test_bridge_was_not_dissolved() {
         .previous_output(input_out_point1)
         .build();
 
-    let outputs = vec![CellOutput::new_builder()
-        .capacity(10u64.pack())
-        .lock(lock_script.clone())
-        .type_(Some(bridge_script.clone()).pack())
-        .build()];
+    let outputs = vec![
+        CellOutput::new_builder()
+            .capacity(5u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity(5u64.pack())
+            .lock(lock_script.clone())
+            .build()
+    ];
 
-    let outputs_data = vec![Bytes::new(); 1];
+    let outputs_data = vec![Bytes::new(); 2];
 
     // in combat the secp256 lock script would check the withness
     // for a signature, hence we can't use an actionByte here.
@@ -1506,15 +1698,13 @@ fn test_bridge_was_not_dissolved() {
     let tx = context.complete_tx(tx);
 
     // run
-    let err = context
+    let cycles = context
         .verify_tx(&tx, MAX_CYCLES)
-        .unwrap_err();
-
-    assert_error_eq!(err, ScriptError::ValidationFailure(19).input_type_script(0));
+        .expect("pass verification");
 }
 
 #[test]
-fn test_leftover_capacity() {
+fn test_not_signed_by_trustee() {
     let mut context = Context::default();
 
     let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
@@ -1539,11 +1729,9 @@ fn test_leftover_capacity() {
         .previous_output(bridge_creation_out_point)
         .build();
 
-    let tx_hash: &[u8] = &*bridge_creation_input.previous_output().tx_hash().raw_data();
-    let index: &[u8] = &*bridge_creation_input.previous_output().index().raw_data();
-    let state_id = Bytes::from([tx_hash, index].concat());
-    let trustee = lock_script.calc_script_hash().raw_data();
-    let type_script_args = Bytes::from([&*state_id, &*trustee, &*validator_list].concat());
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = Bytes::from(Vec::from_hex("9999999999999999999999999999999999999999999999999999999999999999").unwrap());
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
 
     let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
     let bridge_out_point = context.deploy_cell(bridge_bin);
@@ -1583,7 +1771,7 @@ fn test_leftover_capacity() {
             .lock(lock_script.clone())
             .build(),
         CellOutput::new_builder()
-            .capacity(1u64.pack())
+            .capacity(5u64.pack())
             .lock(lock_script.clone())
             .build()
     ];
@@ -1611,7 +1799,648 @@ fn test_leftover_capacity() {
         .verify_tx(&tx, MAX_CYCLES)
         .unwrap_err();
 
-    assert_error_eq!(err, ScriptError::ValidationFailure(20).input_type_script(0));
+    assert_error_eq!(err, ScriptError::ValidationFailure(18).input_type_script(0));
+}
+
+#[test]
+fn test_bridge_was_not_dissolved() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point)
+        .build();
+
+    let validator_list = Bytes::from(Vec::from_hex("1122334411223343241123344112233441122344112233441122334411223344000000000000000000000000112233445566778899001122334455667788990000000000000000000000000000000000000000000000000000000000000004D2AAAAAAAA").unwrap());
+    
+    let bridge_creation_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let bridge_creation_input = CellInput::new_builder()
+        .previous_output(bridge_creation_out_point)
+        .build();
+
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = lock_script.calc_script_hash().raw_data();
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
+
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let bridge_script = context
+        .build_script(&bridge_out_point, type_script_args)
+        .expect("script");
+    let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+    
+    let input_out_point1 = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+
+    let input1 = CellInput::new_builder()
+        .previous_output(input_out_point1)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(10u64.pack())
+        .lock(lock_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+
+    let outputs_data = vec![Bytes::new(); 1];
+
+    // in combat the secp256 lock script would check the withness
+    // for a signature, hence we can't use an actionByte here.
+    let witnesses = vec![Bytes::from(&[2u8][..]), Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_script_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    let err = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(19).input_type_script(0));
+}
+
+#[test]
+fn test_leftover_capacity() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point)
+        .build();
+
+    let validator_list = Bytes::from(Vec::from_hex("1122334411223343241123344112233441122344112233441122334411223344000000000000000000000000112233445566778899001122334455667788990000000000000000000000000000000000000000000000000000000000000004D2AAAAAAAA").unwrap());
+    
+    let bridge_creation_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let bridge_creation_input = CellInput::new_builder()
+        .previous_output(bridge_creation_out_point)
+        .build();
+
+    let state_id = Bytes::from(type_id(&bridge_creation_input, 0).to_vec());
+    let trustee = lock_script.calc_script_hash().raw_data();
+    let type_script_args = Bytes::from([&*state_id, &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
+
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let bridge_script = context
+        .build_script(&bridge_out_point, type_script_args)
+        .expect("script");
+    let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+    
+    let input_out_point1 = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+
+    let input1 = CellInput::new_builder()
+        .previous_output(input_out_point1)
+        .build();
+
+    let outputs = vec![
+        CellOutput::new_builder()
+            .capacity(5u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity(1u64.pack())
+            .lock(lock_script.clone())
+            .build()
+    ];
+
+    let outputs_data = vec![Bytes::new(); 2];
+
+    // in combat the secp256 lock script would check the withness
+    // for a signature, hence we can't use an actionByte here.
+    let witnesses = vec![Bytes::from(&[2u8][..]), Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_script_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    let err = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(20).input_type_script(0));
+}
+
+#[test]
+fn test_bridge_guard_with_bridge_output() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+    // stands in for the bridge type script
+    let bridge_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+        .expect("script");
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let trustee = trustee_script.calc_script_hash().raw_data();
+    let bridge_guard_args: Bytes = Bytes::from([&*bridge_script_hash, &*trustee].concat());
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
+    let bridge_guard_dep = CellDep::new_builder()
+        .out_point(bridge_guard_out_point)
+        .build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(20u64.pack())
+        .lock(bridge_guard_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+    let outputs_data = vec![Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_guard_dep)
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    let cycles = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .expect("pass verification");
+    println!("consume cycles: {}", cycles);
+}
+
+#[test]
+fn test_bridge_guard_dissolve_by_trustee() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+    // stands in for the bridge type script
+    let bridge_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+        .expect("script");
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let trustee = trustee_script.calc_script_hash().raw_data();
+    let bridge_guard_args: Bytes = Bytes::from([&*bridge_script_hash, &*trustee].concat());
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
+    let bridge_guard_dep = CellDep::new_builder()
+        .out_point(bridge_guard_out_point)
+        .build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(trustee_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(20u64.pack())
+        .lock(bridge_guard_script.clone())
+        .build()];
+    let outputs_data = vec![Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_guard_dep)
+        .build();
+    let tx = context.complete_tx(tx);
+
+    // run
+    let cycles = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .expect("pass verification");
+    println!("consume cycles: {}", cycles);
+}
+
+#[test]
+fn test_bridge_guard_bridge_not_in_outputs() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+    // stands in for the bridge type script
+    let bridge_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+        .expect("script");
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let trustee = trustee_script.calc_script_hash().raw_data();
+    let bridge_guard_args: Bytes = Bytes::from([&*bridge_script_hash, &*trustee].concat());
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
+    let bridge_guard_dep = CellDep::new_builder()
+        .out_point(bridge_guard_out_point)
+        .build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(20u64.pack())
+        .lock(bridge_guard_script.clone())
+        .build()];
+    let outputs_data = vec![Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_guard_dep)
+        .build();
+    let tx = context.complete_tx(tx);
+
+    let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(6).input_lock_script(0));
+}
+
+#[test]
+fn test_bridge_guard_wrong_script_args_length() {
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let lock_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let lock_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    // stands in for the bridge type script
+    let bridge_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![2u8]))
+        .expect("script");
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let bridge_guard_args: Bytes = bridge_script_hash.clone();
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
+    let bridge_guard_dep = CellDep::new_builder()
+        .out_point(bridge_guard_out_point)
+        .build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    let outputs = vec![CellOutput::new_builder()
+        .capacity(20u64.pack())
+        .lock(bridge_guard_script.clone())
+        .type_(Some(bridge_script.clone()).pack())
+        .build()];
+    let outputs_data = vec![Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(lock_script_dep)
+        .cell_dep(bridge_guard_dep)
+        .build();
+    let tx = context.complete_tx(tx);
+
+    let err = context.verify_tx(&tx, MAX_CYCLES).unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(5).input_lock_script(0));
+}
+
+// collects a deposit into a guarded bridge cell that sits behind another cell at index 0
+fn bridge_guard_not_at_index_0(bridge_capacity_after: u64) -> (Context, TransactionView) {
+    let validator_list = Bytes::from(Vec::from_hex("f3beac30c498d9e26865f34fcaa57dbb935b0d74").unwrap());
+
+    let mut context = Context::default();
+
+    let always_success_bin: Bytes = Loader::default().load_binary("anyone-can-spend");
+    let always_success_out_point = context.deploy_cell(always_success_bin);
+    let always_success_script = context
+        .build_script(&always_success_out_point, Default::default())
+        .expect("script");
+    let always_success_script_dep = CellDep::new_builder()
+        .out_point(always_success_out_point.clone())
+        .build();
+    let trustee_script = context
+        .build_script(&always_success_out_point, Bytes::from(vec![1u8]))
+        .expect("script");
+
+    let trustee = trustee_script.calc_script_hash().raw_data();
+    let type_script_args = Bytes::from([&[0u8; 32][..], &*trustee, &MIN_DEPOSIT.to_be_bytes()[..], &NO_CONFIG[..], &[VALIDATOR_MODE_LIST][..], &*validator_list].concat());
+
+    let bridge_bin: Bytes = Loader::default().load_binary("parent-bridge");
+    let bridge_out_point = context.deploy_cell(bridge_bin);
+    let bridge_script = context
+        .build_script(&bridge_out_point, type_script_args)
+        .expect("script");
+    let bridge_script_dep = CellDep::new_builder().out_point(bridge_out_point).build();
+
+    let bridge_script_hash = bridge_script.calc_script_hash().raw_data();
+    let bridge_guard_args: Bytes = Bytes::from([&*bridge_script_hash, &*trustee].concat());
+    let bridge_guard_bin: Bytes = Loader::default().load_binary("bridge-guard");
+    let bridge_guard_out_point = context.deploy_cell(bridge_guard_bin);
+    let bridge_guard_script = context
+        .build_script(&bridge_guard_out_point, bridge_guard_args)
+        .expect("script");
+    let bridge_guard_dep = CellDep::new_builder()
+        .out_point(bridge_guard_out_point)
+        .build();
+
+    let input0_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(always_success_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input0 = CellInput::new_builder()
+        .previous_output(input0_out_point)
+        .build();
+
+    let input1_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(100u64.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+        bridge_data(0, 0, 0, checkpoint(0, &[])),
+    );
+    let input1 = CellInput::new_builder()
+        .previous_output(input1_out_point)
+        .build();
+
+    // the deposit lock accepts the cell at input 0
+    let deposit_lock_args: Bytes = Bytes::from([&*always_success_script.calc_script_hash().raw_data(), &*bridge_script_hash].concat());
+    let deposit_lock_bin: Bytes = Loader::default().load_binary("deposit-lock");
+    let deposit_lock_out_point = context.deploy_cell(deposit_lock_bin);
+    let deposit_lock_script = context
+        .build_script(&deposit_lock_out_point, deposit_lock_args)
+        .expect("script");
+    let deposit_lock_dep = CellDep::new_builder()
+        .out_point(deposit_lock_out_point)
+        .build();
+
+    let input2_out_point = context.create_cell(
+        CellOutput::new_builder()
+            .capacity(10u64.pack())
+            .lock(deposit_lock_script.clone())
+            .build(),
+        Default::default(),
+    );
+    let input2 = CellInput::new_builder()
+        .previous_output(input2_out_point)
+        .build();
+
+    let outputs = vec![
+        CellOutput::new_builder()
+            .capacity((120 - bridge_capacity_after).pack())
+            .lock(always_success_script.clone())
+            .build(),
+        CellOutput::new_builder()
+            .capacity(bridge_capacity_after.pack())
+            .lock(bridge_guard_script.clone())
+            .type_(Some(bridge_script.clone()).pack())
+            .build(),
+    ];
+
+    let outputs_data = vec![Bytes::new(), bridge_data(10, 0, 0, checkpoint(0, &[]))];
+
+    let witnesses = vec![Bytes::new(), Bytes::from(&[1u8][..]), Bytes::new()];
+
+    // build transaction
+    let tx = TransactionBuilder::default()
+        .input(input0)
+        .input(input1)
+        .input(input2)
+        .outputs(outputs)
+        .outputs_data(outputs_data.pack())
+        .cell_dep(always_success_script_dep)
+        .cell_dep(bridge_guard_dep)
+        .cell_dep(deposit_lock_dep)
+        .cell_dep(bridge_script_dep)
+        .witnesses(witnesses.pack())
+        .build();
+    let tx = context.complete_tx(tx);
+    (context, tx)
+}
+
+#[test]
+fn test_bridge_guard_bridge_not_at_index_0() {
+    let (context, tx) = bridge_guard_not_at_index_0(110);
+
+    // run
+    let cycles = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .expect("pass verification");
+    println!("consume cycles: {}", cycles);
+}
+
+#[test]
+fn test_bridge_guard_draining_bridge_not_at_index_0() {
+    // the cell at index 0 takes the deposit and part of the bridge
+    let (context, tx) = bridge_guard_not_at_index_0(50);
+
+    // run
+    let err = context
+        .verify_tx(&tx, MAX_CYCLES)
+        .unwrap_err();
+
+    assert_error_eq!(err, ScriptError::ValidationFailure(16).input_type_script(1));
 }
 
 #[test]